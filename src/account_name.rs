@@ -0,0 +1,178 @@
+use std::fmt;
+use std::str;
+use regex::{ Captures, Regex };
+
+/// Separates the components of an account name, e.g. `assets:bank:checking`
+pub const ACCOUNT_SEPARATOR: char = ':';
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct AccountName(String);
+
+impl AccountName {
+    pub fn new(name: String) -> AccountName {
+        AccountName(name)
+    }
+
+    /// Builds an account name from its components
+    pub fn from_components(components: &[&str]) -> AccountName {
+        AccountName(components.join(&ACCOUNT_SEPARATOR.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The colon-separated parts of the account name, from the top level down
+    pub fn components(&self) -> Vec<&str> {
+        if self.0.is_empty() {
+            Vec::new()
+        } else {
+            self.0.split(ACCOUNT_SEPARATOR).collect()
+        }
+    }
+
+    /// The number of components, e.g. 2 for `assets:bank`. The empty name has depth 0.
+    pub fn depth(&self) -> usize {
+        self.components().len()
+    }
+
+    /// The account one level up, or `None` for a top-level account
+    pub fn parent(&self) -> Option<AccountName> {
+        self.0.rfind(ACCOUNT_SEPARATOR).map(|i| AccountName(self.0[..i].to_string()))
+    }
+
+    /// The last component of the account name
+    pub fn leaf(&self) -> &str {
+        match self.0.rfind(ACCOUNT_SEPARATOR) {
+            Some(i) => &self.0[i + 1..],
+            None => &self.0
+        }
+    }
+
+    /// All parent accounts, nearest first
+    pub fn ancestors(&self) -> Vec<AccountName> {
+        let mut r = Vec::new();
+        let mut a = self.parent();
+        while let Some(p) = a {
+            a = p.parent();
+            r.push(p);
+        }
+        r
+    }
+
+    /// Whether this account lies strictly below `other` in the account tree
+    pub fn is_subaccount_of(&self, other: &AccountName) -> bool {
+        self.0.len() > other.0.len()
+            && self.0.starts_with(&other.0)
+            && self.0[other.0.len()..].starts_with(ACCOUNT_SEPARATOR)
+    }
+
+    /// Cuts the account name down to at most `depth` components
+    pub fn clip(&self, depth: usize) -> AccountName {
+        AccountName::from_components(&self.components().into_iter().take(depth).collect::<Vec<&str>>())
+    }
+}
+
+impl fmt::Display for AccountName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Rewrites account names, as declared by `alias` directives
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum AccountAlias {
+    /// Renames an account and all of its subaccounts
+    Literal(AccountName, AccountName),
+    /// Replaces every case-insensitive match of the regex, where `\1` and
+    /// so on in the replacement refer to capture groups
+    Regex(Regex, String)
+}
+
+impl AccountAlias {
+    pub fn apply(&self, account: &AccountName) -> AccountName {
+        match *self {
+            AccountAlias::Literal(ref old, ref new) => {
+                if account == old {
+                    new.clone()
+                } else if account.is_subaccount_of(old) {
+                    AccountName(format!("{}{}", new.0, &account.0[old.0.len()..]))
+                } else {
+                    account.clone()
+                }
+            },
+            AccountAlias::Regex(ref re, ref replacement) => {
+                AccountName(re.replace_all(&account.0, |caps: &Captures| expand_captures(caps, replacement)))
+            }
+        }
+    }
+}
+
+/// Substitutes `\N` in a replacement with the text of capture group N
+fn expand_captures(caps: &Captures, replacement: &str) -> String {
+    let mut r = String::new();
+    let mut chars = replacement.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek().and_then(|d| d.to_digit(10))) {
+            ('\\', Some(n)) => {
+                chars.next();
+                r.push_str(caps.at(n as usize).unwrap_or(""));
+            },
+            _ => r.push(c)
+        }
+    }
+    r
+}
+
+/// Parses an alias as written after `alias` in a journal or given on the
+/// command line: `old = new` or `/regex/ = replacement`
+impl str::FromStr for AccountAlias {
+    type Err = String;
+    fn from_str(s: &str) -> Result<AccountAlias, String> {
+        let s = s.trim();
+        if s.starts_with('/') {
+            let end = try!(s[1..].find('/').map(|i| i + 1).ok_or(format!("unterminated regular expression in alias '{}'", s)));
+            let rest = s[end + 1..].trim_left();
+            if !rest.starts_with('=') {
+                return Err(format!("expected '=' in alias '{}'", s));
+            }
+            let re = try!(Regex::new(&format!("(?i){}", &s[1..end])).map_err(|e| format!("invalid regular expression in alias '{}': {}", s, e)));
+            Ok(AccountAlias::Regex(re, rest[1..].trim().to_string()))
+        } else {
+            match s.find('=') {
+                Some(i) if !s[..i].trim().is_empty() && !s[i + 1..].trim().is_empty() =>
+                    Ok(AccountAlias::Literal(AccountName(s[..i].trim().to_string()), AccountName(s[i + 1..].trim().to_string()))),
+                _ => Err(format!("expected 'old = new' in alias '{}'", s))
+            }
+        }
+    }
+}
+
+#[test]
+fn account_name_test() {
+    let a = AccountName::new(String::from("assets:bank:checking"));
+    assert_eq!(a.depth(), 3);
+    assert_eq!(a.leaf(), "checking");
+    assert_eq!(a.parent(), Some(AccountName::new(String::from("assets:bank"))));
+    assert_eq!(a.ancestors(), vec!(AccountName::new(String::from("assets:bank")), AccountName::new(String::from("assets"))));
+    assert!(a.is_subaccount_of(&AccountName::new(String::from("assets"))));
+    assert!(!a.is_subaccount_of(&AccountName::new(String::from("ass"))));
+    assert!(!a.is_subaccount_of(&a));
+    assert_eq!(a.clip(2), AccountName::new(String::from("assets:bank")));
+    assert_eq!(AccountName::new(String::new()).depth(), 0);
+}
+
+#[test]
+fn account_alias_test() {
+    let apply = |alias: &str, account: &str| {
+        alias.parse::<AccountAlias>().unwrap().apply(&AccountName::new(account.to_string())).as_str().to_string()
+    };
+    assert_eq!(apply("checking = assets:bank:checking", "checking"), "assets:bank:checking");
+    assert_eq!(apply("checking = assets:bank:checking", "checking:joint"), "assets:bank:checking:joint");
+    assert_eq!(apply("checking = assets:bank:checking", "checkings"), "checkings");
+    assert_eq!(apply("/^expenses:(\\w+):(\\w+)$/ = expenses:\\2:\\1", "Expenses:food:dining"), "expenses:dining:food");
+    assert_eq!(apply("/bank/ = BANK", "assets:bank:bankruptcy"), "assets:BANK:BANKruptcy");
+    assert_eq!(apply("/^a$/ = b/c", "a"), "b/c");
+    assert!("/unterminated = x".parse::<AccountAlias>().is_err());
+    assert!("no equals".parse::<AccountAlias>().is_err());
+}
@@ -1,29 +1,36 @@
 use std::ops::Add;
 use std::ops::Div;
+use std::ops::Mul;
+use std::ops::Neg;
+use std::ops::Sub;
 use std::num::Zero;
 use std::rc::Rc;
+use std::cmp;
+use std::fmt;
+use std::hash;
 use commodity::Commodity;
-use decimal::Decimal;
+use decimal::{ Decimal, Rounding };
 use quantity::Quantity;
 use price::Price;
+use lot::Lot;
 use std::iter::FromIterator;
 
 #[derive(Clone, PartialEq, Eq)]
-enum Side {
+pub enum Side {
     Left,
     Right
 }
 
 #[derive(Clone, PartialEq, Eq)]
-struct DigitGroupStyle(char, Vec<usize>);
+pub struct DigitGroupStyle(pub char, pub Vec<usize>);
 
 #[derive(Clone, PartialEq, Eq)]
 pub struct AmountStyle {
-    commodity_side: Side,
-    commodity_spaced: bool,
-    precision: usize,
-    decimal_point: Option<char>,
-    digit_groups: Option<DigitGroupStyle>
+    pub commodity_side: Side,
+    pub commodity_spaced: bool,
+    pub precision: usize,
+    pub decimal_point: Option<char>,
+    pub digit_groups: Option<DigitGroupStyle>
 }
 
 impl AmountStyle {
@@ -38,14 +45,45 @@ impl AmountStyle {
     }
 }
 
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone)]
 pub struct Amount {
     pub commodity: Commodity,
     pub quantity: Quantity,
     pub price: Rc<Price>,
+    pub lot: Option<Rc<Lot>>,
     pub style: AmountStyle
 }
 
+/// Amounts compare by value: commodity, quantity, price and lot, but not
+/// the style they are shown in, so `1.0 EUR == 1.00 EUR`
+impl Ord for Amount {
+    fn cmp(&self, other: &Amount) -> cmp::Ordering {
+        (&self.commodity, &self.quantity, &self.price, &self.lot).cmp(&(&other.commodity, &other.quantity, &other.price, &other.lot))
+    }
+}
+
+impl PartialOrd for Amount {
+    fn partial_cmp(&self, other: &Amount) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Amount {
+    fn eq(&self, other: &Amount) -> bool {
+        self.cmp(other) == cmp::Ordering::Equal
+    }
+}
+
+impl Eq for Amount {}
+
+impl hash::Hash for Amount {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        self.commodity.hash(state);
+        self.quantity.hash(state);
+        self.price.hash(state);
+    }
+}
+
 #[derive(Clone, PartialEq, Eq)]
 pub struct MixedAmount(pub Vec<Amount>);
 
@@ -55,31 +93,109 @@ impl Amount {
             commodity: Commodity::new(String::from("")),
             quantity: Quantity(Decimal::new(0, 0)),
             price: Rc::new(Price::None),
+            lot: None,
             style: AmountStyle::new()
         }
     }
 
+    /// Converts the amount into the commodity of its price. A unit price is
+    /// multiplied by the quantity, while a total price already covers the
+    /// whole quantity and only takes on its sign. The result is shown in the
+    /// style of the price. Without a transaction price the lot price is used;
+    /// amounts with neither are returned unchanged.
     pub fn cost(&self) -> Amount {
-        let q = self.clone().quantity;
-        let res = Rc::try_unwrap(self.price.clone());
-        match res.ok().unwrap() {
+        match *self.price {
+            Price::None => match self.lot {
+                Some(ref lot) => self.cost_at(&lot.price),
+                None => self.clone()
+            },
+            ref p => self.cost_at(p)
+        }
+    }
+
+    fn cost_at(&self, price: &Price) -> Amount {
+        match *price {
             Price::None => self.clone(),
-            Price::UnitPrice(a) => {
-                let mut r = a;
-                r.quantity = r.quantity * q;
+            Price::UnitPrice(ref p) => {
+                let mut r = p.with_commodity(p.commodity.clone());
+                r.quantity = p.quantity.clone() * self.quantity.clone();
                 r
             },
-            Price::TotalPrice(a) => {
-                let mut r = a;
-                r.quantity = r.quantity * q;
+            Price::TotalPrice(ref p) => {
+                let mut r = p.with_commodity(p.commodity.clone());
+                r.quantity = if self.is_negative() { -p.quantity.clone().abs() } else { p.quantity.clone().abs() };
                 r
             }
         }
     }
 
     pub fn is_negative(&self) -> bool {
-        let z = Decimal::new(0, 0);
-        self.quantity.0 < z
+        self.quantity.is_negative()
+    }
+
+    /// Shows the quantity followed by the commodity symbol, ignoring the amount's style
+    pub fn show_unstyled(&self) -> String {
+        format!("{} {}", self.quantity.0, self.commodity.symbol()).trim().to_string()
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.quantity.is_zero()
+    }
+
+    /// Rounds the quantity to `precision` places, which also becomes the
+    /// precision the amount is shown with
+    pub fn round(&self, precision: usize, mode: Rounding) -> Amount {
+        let mut r = self.clone();
+        r.quantity = Quantity(self.quantity.0.round(precision as u32, mode));
+        r.style.precision = precision;
+        r
+    }
+
+    /// Rounds the quantity to the precision of the amount's style
+    pub fn round_to_style(&self, mode: Rounding) -> Amount {
+        self.round(self.style.precision, mode)
+    }
+
+    /// The amount with its precision raised to show every decimal place of
+    /// the quantity, e.g. for error messages
+    pub fn with_full_precision(&self) -> Amount {
+        let mut r = self.clone();
+        r.style.precision = cmp::max(self.style.precision, self.quantity.0.normalize().scale as usize);
+        r
+    }
+
+    /// Whether two amounts can be added without conversion, i.e. they share
+    /// the commodity, price and lot
+    pub fn is_similar(&self, other: &Amount) -> bool {
+        self.commodity == other.commodity && self.price == other.price && self.lot == other.lot
+    }
+
+    /// Adds an amount of the same commodity, keeping this amount's price and
+    /// style at the larger of the two precisions. Total prices are summed.
+    fn add_similar(&self, other: &Amount) -> Amount {
+        let mut r = self.clone();
+        r.quantity = self.quantity.clone() + other.quantity.clone();
+        if other.style.precision > r.style.precision {
+            r.style.precision = other.style.precision;
+        }
+        if let (&Price::TotalPrice(ref a), &Price::TotalPrice(ref b)) = (self.price.as_ref(), other.price.as_ref()) {
+            let mut p = a.clone();
+            p.quantity = a.quantity.clone() + b.quantity.clone();
+            r.price = Rc::new(Price::TotalPrice(p));
+        }
+        r
+    }
+
+    /// Scales the amount's quantity, along with its price if it is a total price
+    fn scale<F>(&self, f: F) -> Amount where F : Fn(Quantity) -> Quantity {
+        let mut r = self.clone();
+        r.quantity = f(self.quantity.clone());
+        if let &Price::TotalPrice(ref p) = self.price.as_ref() {
+            let mut p = p.clone();
+            p.quantity = f(p.quantity).abs();
+            r.price = Rc::new(Price::TotalPrice(p));
+        }
+        r
     }
 
     pub fn apply_op<F>(op: F, a: Amount, b: Amount) -> Amount where F : Fn(Quantity, Quantity) -> Quantity {
@@ -87,6 +203,7 @@ impl Amount {
             commodity: b.commodity,
             style: AmountStyle::new(),
             price: Rc::new(Price::None),
+            lot: None,
             quantity: op(a.quantity, b.quantity)
         }
     }
@@ -96,17 +213,119 @@ impl Amount {
             commodity: commodity,
             style: self.style.clone(),
             quantity: self.quantity.clone(),
-            price: Rc::new(Price::None)
+            price: Rc::new(Price::None),
+            lot: None
         }
     }
 }
 
+impl Amount {
+    /// Shows the amount in its style, e.g. `$1,234.50`, `1.234,50 EUR` or
+    /// `-€5`, rounding half-even to the style's precision. With `show_price`
+    /// the lot annotation and price follow as they would in a journal.
+    pub fn show(&self, show_price: bool) -> String {
+        let rounded = self.quantity.0.round(self.style.precision as u32, Rounding::HalfEven);
+        let sign = if rounded.is_negative() { "-" } else { "" };
+        let number = show_number(&rounded.abs(), &self.style);
+        let commodity = self.commodity.show();
+        let space = if self.style.commodity_spaced && !commodity.is_empty() { " " } else { "" };
+        let mut r = match self.style.commodity_side {
+            Side::Left => format!("{}{}{}{}", sign, commodity, space, number),
+            Side::Right => format!("{}{}{}{}", sign, number, space, commodity)
+        };
+        if show_price {
+            if let Some(ref lot) = self.lot {
+                match lot.price {
+                    Price::UnitPrice(ref p) => r = r + &format!(" {{{}}}", p.show(false)),
+                    Price::TotalPrice(ref p) => r = r + &format!(" {{{{{}}}}}", p.show(false)),
+                    Price::None => ()
+                }
+                if let Some(d) = lot.date {
+                    r = r + &format!(" [{}]", d.format("%Y/%m/%d"));
+                }
+                if let Some(ref n) = lot.note {
+                    r = r + &format!(" ({})", n);
+                }
+            }
+            match *self.price {
+                Price::UnitPrice(ref p) => r = r + " @ " + &p.show(false),
+                Price::TotalPrice(ref p) => r = r + " @@ " + &p.show(false),
+                Price::None => ()
+            }
+        }
+        r
+    }
+}
+
+/// Shows a non-negative number with the style's decimal mark and digit groups
+fn show_number(n: &Decimal, style: &AmountStyle) -> String {
+    let mut digits = format!("{}", n.unscaled);
+    let scale = n.scale as usize;
+    while digits.len() <= scale {
+        digits.insert(0, '0');
+    }
+    let (int_part, frac_part) = digits.split_at(digits.len() - scale);
+    let int_part = match style.digit_groups {
+        Some(ref g) => group_digits(int_part, g),
+        None => int_part.to_string()
+    };
+    if frac_part.is_empty() {
+        return int_part;
+    }
+    let mark = match (style.decimal_point, &style.digit_groups) {
+        (Some(c), _) => c,
+        (None, &Some(DigitGroupStyle('.', _))) => ',',
+        _ => '.'
+    };
+    format!("{}{}{}", int_part, mark, frac_part)
+}
+
+/// Separates digits into groups, the sizes given from the decimal mark
+/// leftwards with the last size repeating
+fn group_digits(digits: &str, style: &DigitGroupStyle) -> String {
+    let DigitGroupStyle(separator, ref sizes) = *style;
+    let mut sizes = sizes.iter().cloned().filter(|&x| x > 0);
+    let mut size = match sizes.next() {
+        Some(x) => x,
+        None => return digits.to_string()
+    };
+    let mut groups = Vec::new();
+    let mut rest = digits;
+    while rest.len() > size {
+        groups.push(&rest[rest.len() - size..]);
+        rest = &rest[..rest.len() - size];
+        size = sizes.next().unwrap_or(size);
+    }
+    groups.push(rest);
+    groups.reverse();
+    groups.join(&separator.to_string())
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.show(true))
+    }
+}
+
 impl Div<Quantity> for Amount {
     type Output = Amount;
-    fn div(self, Quantity(d): Quantity) -> Amount {
-        let Quantity(q) = self.clone().quantity;
-        let mut r = self.clone();
-        r.quantity = Quantity(q / d);
+    fn div(self, d: Quantity) -> Amount {
+        self.scale(|q| q / d.clone())
+    }
+}
+
+impl Mul<Quantity> for Amount {
+    type Output = Amount;
+    fn mul(self, m: Quantity) -> Amount {
+        self.scale(|q| q * m.clone())
+    }
+}
+
+impl Neg for Amount {
+    type Output = Amount;
+    fn neg(self) -> Amount {
+        let mut r = self;
+        r.quantity = -r.quantity;
         r
     }
 }
@@ -116,17 +335,103 @@ impl MixedAmount {
         MixedAmount(self.0.iter().filter(|&x| f(x)).map(|x| x.clone()).collect())
     }
 
+    /// The amounts in the given commodity
     pub fn filter_by_commodity(&self, commodity: Commodity) -> MixedAmount {
-        let r = match &self.0.iter().filter(|x| x.commodity == commodity).map(|x| x.clone()).collect::<Vec<Amount>>() {
-            x if x == &vec!() => vec!(Amount::new()),
-            _ => vec!()
-        };
-        MixedAmount(r)
+        self.clone().filter(|x| x.commodity == commodity)
+    }
+
+    /// Combines amounts with the same commodity and price and drops the ones
+    /// that are zero. Amounts keep the order in which their commodity first appears.
+    pub fn normalise(&self) -> MixedAmount {
+        MixedAmount::combine(&self.0, |a, b| a.is_similar(b))
+    }
+
+    /// Like `normalise`, but combines amounts of the same commodity regardless
+    /// of their prices, keeping the first price seen
+    pub fn normalise_squashing_prices(&self) -> MixedAmount {
+        MixedAmount::combine(&self.0, |a, b| a.commodity == b.commodity)
+    }
+
+    fn combine<F>(amounts: &Vec<Amount>, similar: F) -> MixedAmount where F : Fn(&Amount, &Amount) -> bool {
+        let mut r: Vec<Amount> = Vec::new();
+        for a in amounts {
+            let pos = r.iter().position(|x| similar(x, a));
+            match pos {
+                Some(i) => r[i] = r[i].add_similar(a),
+                None => r.push(a.clone())
+            }
+        }
+        MixedAmount(r.into_iter().filter(|x| !x.is_zero()).collect())
+    }
+
+    /// Shows every amount unstyled, separated by commas; zero shows as `0`
+    pub fn show_unstyled(&self) -> String {
+        if self.0.is_empty() {
+            String::from("0")
+        } else {
+            self.0.iter().map(|x| x.show_unstyled()).collect::<Vec<String>>().join(", ")
+        }
+    }
+
+    /// The total amount held in the given commodity, if any
+    pub fn get(&self, commodity: &Commodity) -> Option<Amount> {
+        self.filter_by_commodity(commodity.clone()).normalise_squashing_prices().0.into_iter().next()
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.normalise_squashing_prices().0.is_empty()
+    }
+
+    /// Whether the amount is non-zero and negative in every commodity it holds
+    pub fn is_negative(&self) -> bool {
+        let n = self.normalise_squashing_prices();
+        !n.0.is_empty() && n.0.iter().all(|x| x.is_negative())
     }
 
     pub fn cost(&self) -> MixedAmount {
         MixedAmount(self.0.iter().map(|x| x.cost()).collect())
     }
+
+    pub fn round(&self, precision: usize, mode: Rounding) -> MixedAmount {
+        MixedAmount(self.0.iter().map(|x| x.round(precision, mode)).collect())
+    }
+
+    /// Rounds every amount to the precision of its own style
+    pub fn round_to_style(&self, mode: Rounding) -> MixedAmount {
+        MixedAmount(self.0.iter().map(|x| x.round_to_style(mode)).collect())
+    }
+
+    pub fn with_full_precision(&self) -> MixedAmount {
+        MixedAmount(self.0.iter().map(|x| x.with_full_precision()).collect())
+    }
+}
+
+impl MixedAmount {
+    /// Shows the amounts in their styles separated by commas; zero shows as `0`
+    pub fn show_single_line(&self, show_price: bool) -> String {
+        if self.0.is_empty() {
+            String::from("0")
+        } else {
+            self.0.iter().map(|x| x.show(show_price)).collect::<Vec<String>>().join(", ")
+        }
+    }
+
+    /// Shows each amount on its own line, right-aligned to the widest
+    pub fn show_multi_line(&self, show_price: bool) -> String {
+        let lines: Vec<String> = if self.0.is_empty() {
+            vec!(String::from("0"))
+        } else {
+            self.0.iter().map(|x| x.show(show_price)).collect()
+        };
+        let width = lines.iter().map(|x| x.chars().count()).max().unwrap_or(0);
+        lines.iter().map(|x| format!("{:>1$}", x, width)).collect::<Vec<String>>().join("\n")
+    }
+}
+
+impl fmt::Display for MixedAmount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.show_single_line(true))
+    }
 }
 
 impl Zero for MixedAmount {
@@ -139,7 +444,39 @@ impl Add for MixedAmount {
     type Output = MixedAmount;
 
     fn add(self, rhs: MixedAmount) -> MixedAmount {
-        MixedAmount(self.0.iter().chain(rhs.0.iter()).map(|x| x.clone()).collect())
+        MixedAmount(self.0.into_iter().chain(rhs.0.into_iter()).collect()).normalise()
+    }
+}
+
+impl Sub for MixedAmount {
+    type Output = MixedAmount;
+
+    fn sub(self, rhs: MixedAmount) -> MixedAmount {
+        self + -rhs
+    }
+}
+
+impl Neg for MixedAmount {
+    type Output = MixedAmount;
+
+    fn neg(self) -> MixedAmount {
+        MixedAmount(self.0.into_iter().map(|x| -x).collect())
+    }
+}
+
+impl Mul<Quantity> for MixedAmount {
+    type Output = MixedAmount;
+
+    fn mul(self, m: Quantity) -> MixedAmount {
+        MixedAmount(self.0.into_iter().map(|x| x * m.clone()).collect()).normalise()
+    }
+}
+
+impl Div<Quantity> for MixedAmount {
+    type Output = MixedAmount;
+
+    fn div(self, d: Quantity) -> MixedAmount {
+        MixedAmount(self.0.into_iter().map(|x| x / d.clone()).collect()).normalise()
     }
 }
 
@@ -148,3 +485,119 @@ impl FromIterator<Amount> for MixedAmount {
         MixedAmount(Vec::from_iter(iterable))
     }
 }
+
+#[cfg(test)]
+fn amount(q: i128, scale: u32, commodity: &str) -> Amount {
+    let mut a = Amount::new();
+    a.commodity = Commodity::new(String::from(commodity));
+    a.quantity = Quantity(Decimal::new(q, scale));
+    a
+}
+
+#[test]
+fn mixed_amount_arithmetic_test() {
+    let usd = Commodity::new(String::from("USD"));
+    let a = MixedAmount(vec!(amount(1050, 2, "USD"), amount(3, 0, "EUR")));
+    let b = MixedAmount(vec!(amount(-3, 0, "EUR"), amount(25, 1, "USD")));
+
+    let sum = a.clone() + b.clone();
+    assert!(sum.0.len() == 1);
+    assert!(sum.get(&usd).unwrap().quantity == Quantity(Decimal::new(1300, 2)));
+    assert!((a.clone() - a.clone()).is_zero());
+    assert!((-a.clone()).get(&usd).unwrap().quantity == Quantity(Decimal::new(-1050, 2)));
+    assert!(!a.is_negative() && (-sum.clone()).is_negative());
+    assert!((sum.clone() * Quantity(Decimal::new(2, 0))).get(&usd).unwrap().quantity == Quantity(Decimal::new(2600, 2)));
+    assert!(a.filter_by_commodity(usd.clone()).0 == vec!(amount(1050, 2, "USD")));
+
+    let mut priced = amount(1, 0, "USD");
+    priced.price = Rc::new(Price::UnitPrice(amount(2, 0, "EUR")));
+    assert!((MixedAmount(vec!(priced.clone())) + MixedAmount(vec!(amount(1, 0, "USD")))).0.len() == 2);
+    assert!(MixedAmount(vec!(priced, amount(1, 0, "USD"))).normalise_squashing_prices().0.len() == 1);
+}
+
+#[test]
+fn cost_test() {
+    let mut price = amount(150, 2, "USD");
+    price.style.precision = 2;
+    price.style.commodity_side = Side::Left;
+
+    let mut a = amount(-10, 0, "AAPL");
+    a.price = Rc::new(Price::UnitPrice(price.clone()));
+    let shared = a.price.clone();
+    let c = a.cost();
+    assert!(c.commodity == Commodity::new(String::from("USD")));
+    assert!(c.quantity == Quantity(Decimal::new(-1500, 2)));
+    assert!(c.style == price.style);
+    assert!(*c.price == Price::None);
+    assert!(Rc::strong_count(&shared) == 2);
+
+    a.price = Rc::new(Price::TotalPrice(amount(15, 0, "USD")));
+    assert!(a.cost().quantity == Quantity(Decimal::new(-15, 0)));
+    assert!((-a.clone()).cost().quantity == Quantity(Decimal::new(15, 0)));
+    assert!(amount(3, 0, "EUR").cost() == amount(3, 0, "EUR"));
+}
+
+#[test]
+fn round_test() {
+    let mut a = amount(-12345, 3, "USD");
+    a.style.precision = 2;
+    assert!(a.round_to_style(Rounding::HalfEven).quantity == Quantity(Decimal::new(-1234, 2)));
+    assert!(a.round_to_style(Rounding::HalfUp).quantity == Quantity(Decimal::new(-1235, 2)));
+    assert!(a.round_to_style(Rounding::Ceiling).quantity == Quantity(Decimal::new(-1234, 2)));
+    let b = a.round(0, Rounding::Floor);
+    assert!(b.quantity == Quantity(Decimal::new(-13, 0)) && b.style.precision == 0);
+
+    let m = MixedAmount(vec!(a, amount(5, 1, "EUR"))).round(0, Rounding::HalfEven);
+    assert!(m.0[1].quantity == Quantity(Decimal::new(0, 0)));
+}
+
+#[test]
+fn display_test() {
+    use parser::parse_amount;
+    let show = |s: &str| format!("{}", parse_amount(s).unwrap());
+    assert_eq!(show("$1,234.50"), "$1,234.50");
+    assert_eq!(show("1.234,50 EUR"), "1.234,50 EUR");
+    assert_eq!(show("€-5"), "-€5");
+    assert_eq!(show("1,00,000.00 INR"), "1,00,000.00 INR");
+    assert_eq!(show("\"AAPL 2\" 3"), "\"AAPL 2\" 3");
+
+    let mut a = parse_amount("$10.25").unwrap();
+    a.style.precision = 1;
+    assert_eq!(format!("{}", a), "$10.2");
+    a.style.precision = 3;
+    assert_eq!(format!("{}", -a), "-$10.250");
+
+    let mut priced = parse_amount("10 AAPL").unwrap();
+    priced.price = Rc::new(Price::UnitPrice(parse_amount("$150.00").unwrap()));
+    assert_eq!(priced.show(true), "10 AAPL @ $150.00");
+    assert_eq!(priced.show(false), "10 AAPL");
+
+    let m = MixedAmount(vec!(parse_amount("$1,000.00").unwrap(), parse_amount("5 EUR").unwrap()));
+    assert_eq!(format!("{}", m), "$1,000.00, 5 EUR");
+    assert_eq!(m.show_multi_line(false), "$1,000.00\n    5 EUR");
+    assert_eq!(format!("{}", MixedAmount(vec!())), "0");
+}
+
+#[test]
+fn amount_key_test() {
+    use std::collections::{ BTreeMap, HashMap };
+    let (a, b) = (amount(10, 1, "EUR"), amount(100, 2, "EUR"));
+    assert!(a == b && a.style == b.style);
+    let mut c = b.clone();
+    c.style.precision = 2;
+    assert!(a == c);
+
+    let mut hashed = HashMap::new();
+    hashed.insert(a.clone(), "a");
+    hashed.insert(c.clone(), "c");
+    assert!(hashed.len() == 1 && hashed[&a] == "c");
+    let mut ordered = BTreeMap::new();
+    ordered.insert(a.clone(), "a");
+    ordered.insert(c, "c");
+    ordered.insert(amount(1, 0, "USD"), "usd");
+    assert!(ordered.len() == 2 && ordered[&a] == "c");
+
+    let mut priced = a.clone();
+    priced.price = Rc::new(Price::UnitPrice(amount(2, 0, "USD")));
+    assert!(priced != a);
+}
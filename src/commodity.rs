@@ -1,4 +1,9 @@
-#[derive(Clone, PartialEq, Eq)]
+use chrono::{ Date, Local };
+use decimal::Decimal;
+use parser;
+use price::PriceDb;
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub struct Commodity(String);
 
 impl Commodity {
@@ -6,7 +11,22 @@ impl Commodity {
         return Commodity(commodity);
     }
 
-    pub fn conversion_rate(Commodity(a): Commodity, Commodity(b) : Commodity) -> f64 {
-        return 1.0;
+    pub fn symbol(&self) -> &str {
+        &self.0
+    }
+
+    /// The symbol as written in a journal, quoted if it contains characters
+    /// that would otherwise end it
+    pub fn show(&self) -> String {
+        if self.0.chars().all(parser::is_commodity_char) {
+            self.0.clone()
+        } else {
+            format!("\"{}\"", self.0)
+        }
+    }
+
+    /// The price of one unit of this commodity in `to` on the given date
+    pub fn conversion_rate(&self, to: &Commodity, date: &Date<Local>, prices: &PriceDb) -> Option<Decimal> {
+        prices.rate(self, to, date)
     }
 }
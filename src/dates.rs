@@ -0,0 +1,379 @@
+use chrono::{ Date, Datelike, Duration, Local, TimeZone };
+
+use parser;
+
+/// A span of days. The start is inclusive and the end exclusive; a missing
+/// bound leaves the span open on that side.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DateSpan {
+    pub start: Option<Date<Local>>,
+    pub end: Option<Date<Local>>
+}
+
+impl DateSpan {
+    pub fn new(start: Option<Date<Local>>, end: Option<Date<Local>>) -> DateSpan {
+        DateSpan { start: start, end: end }
+    }
+
+    /// The span open on both ends, containing every date
+    pub fn everything() -> DateSpan {
+        DateSpan::new(None, None)
+    }
+
+    /// The span containing just the given day
+    pub fn day(date: Date<Local>) -> DateSpan {
+        DateSpan::new(Some(date), Some(date + Duration::days(1)))
+    }
+
+    pub fn contains(&self, date: &Date<Local>) -> bool {
+        self.start.map_or(true, |s| s <= *date) && self.end.map_or(true, |e| *date < e)
+    }
+
+    /// The dates contained in both spans
+    pub fn intersect(&self, other: &DateSpan) -> DateSpan {
+        let start = match (self.start, other.start) {
+            (Some(a), Some(b)) => Some(if a > b { a } else { b }),
+            (a, b) => a.or(b)
+        };
+        let end = match (self.end, other.end) {
+            (Some(a), Some(b)) => Some(if a < b { a } else { b }),
+            (a, b) => a.or(b)
+        };
+        DateSpan::new(start, end)
+    }
+
+    /// The smallest span containing both spans
+    pub fn union(&self, other: &DateSpan) -> DateSpan {
+        let start = match (self.start, other.start) {
+            (Some(a), Some(b)) => Some(if a < b { a } else { b }),
+            _ => None
+        };
+        let end = match (self.end, other.end) {
+            (Some(a), Some(b)) => Some(if a > b { a } else { b }),
+            _ => None
+        };
+        DateSpan::new(start, end)
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.start.is_none() || self.end.is_none()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Period {
+    Day,
+    Week,
+    Month,
+    Quarter,
+    Year
+}
+
+impl Period {
+    fn from_str(s: &str) -> Option<Period> {
+        match s {
+            "day" => Some(Period::Day),
+            "week" => Some(Period::Week),
+            "month" => Some(Period::Month),
+            "quarter" => Some(Period::Quarter),
+            "year" => Some(Period::Year),
+            _ => None
+        }
+    }
+
+    /// The first day of the period containing `date`
+    pub fn start_of(&self, date: Date<Local>) -> Date<Local> {
+        match *self {
+            Period::Day => date,
+            Period::Week => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+            Period::Month => Local.ymd(date.year(), date.month(), 1),
+            Period::Quarter => Local.ymd(date.year(), (date.month() - 1) / 3 * 3 + 1, 1),
+            Period::Year => Local.ymd(date.year(), 1, 1)
+        }
+    }
+
+    /// Moves `date` by `n` periods. Month-based periods keep the day of the
+    /// month where possible and clip it to the end of shorter months.
+    pub fn add(&self, date: Date<Local>, n: i64) -> Date<Local> {
+        match *self {
+            Period::Day => date + Duration::days(n),
+            Period::Week => date + Duration::weeks(n),
+            Period::Month => add_months(date, n),
+            Period::Quarter => add_months(date, n * 3),
+            Period::Year => add_months(date, n * 12)
+        }
+    }
+
+    /// The whole period containing `date`
+    pub fn span_of(&self, date: Date<Local>) -> DateSpan {
+        let start = self.start_of(date);
+        DateSpan::new(Some(start), Some(self.add(start, 1)))
+    }
+}
+
+fn add_months(date: Date<Local>, n: i64) -> Date<Local> {
+    let months = date.year() as i64 * 12 + date.month0() as i64 + n;
+    let (y, m) = ((months / 12) as i32, (months % 12) as u32 + 1);
+    let mut d = date.day();
+    loop {
+        match Local.ymd_opt(y, m, d).single() {
+            Some(r) => return r,
+            None => d -= 1
+        }
+    }
+}
+
+const MONTH_NAMES: [&'static str; 12] = ["january", "february", "march", "april", "may", "june", "july",
+                                          "august", "september", "october", "november", "december"];
+
+fn parse_month_name(s: &str) -> Option<u32> {
+    if s.len() < 3 {
+        return None;
+    }
+    MONTH_NAMES.iter().position(|m| m.starts_with(s)).map(|i| i as u32 + 1)
+}
+
+/// Resolves a smart date to the span of days it denotes, relative to `day`.
+///
+/// Accepts full and partial dates (`2016/03/05`, `03/05`), years (`2016`),
+/// months (`2016/03`, `march`), `today`, `yesterday`, `tomorrow` and
+/// `this`/`last`/`next` followed by `day`, `week`, `month`, `quarter` or `year`.
+pub fn parse_smart_date(day: Date<Local>, text: &str) -> Option<DateSpan> {
+    let text = text.trim().to_lowercase();
+    let words: Vec<&str> = text.split_whitespace().collect();
+    match words.as_slice() {
+        [ref w] => parse_smart_date_word(day, w),
+        [ref rel, ref p] => {
+            let n = match *rel {
+                "this" => 0,
+                "last" => -1,
+                "next" => 1,
+                _ => return None
+            };
+            Period::from_str(p).map(|p| p.span_of(p.add(day, n)))
+        },
+        _ => None
+    }
+}
+
+fn parse_smart_date_word(day: Date<Local>, w: &str) -> Option<DateSpan> {
+    match w {
+        "today" => return Some(DateSpan::day(day)),
+        "yesterday" => return Some(DateSpan::day(day - Duration::days(1))),
+        "tomorrow" => return Some(DateSpan::day(day + Duration::days(1))),
+        _ => ()
+    }
+    if let Some(m) = parse_month_name(w) {
+        return Some(Period::Month.span_of(Local.ymd(day.year(), m, 1)));
+    }
+    if !w.chars().all(|c| c.is_digit(10) || c == '/' || c == '-' || c == '.') {
+        return None;
+    }
+    if w.chars().all(|c| c.is_digit(10)) {
+        return match w.len() {
+            4 => w.parse().ok().and_then(|y| Local.ymd_opt(y, 1, 1).single()).map(|d| Period::Year.span_of(d)),
+            8 => parser::parse_date(&format!("{}/{}/{}", &w[..4], &w[4..6], &w[6..]), Some(day.year())).map(DateSpan::day),
+            _ => None
+        };
+    }
+    let parts: Vec<&str> = w.split(|c| c == '/' || c == '-' || c == '.').collect();
+    if parts.len() == 2 && parts[0].len() == 4 {
+        match (parts[0].parse(), parts[1].parse()) {
+            (Ok(y), Ok(m)) => Local.ymd_opt(y, m, 1).single().map(|d| Period::Month.span_of(d)),
+            _ => None
+        }
+    } else {
+        parser::parse_date(w, Some(day.year())).map(DateSpan::day)
+    }
+}
+
+/// Parses a date span such as `2016`, `last month`, `from 2016/1/1`,
+/// `to 2016/4/1` or `from 2016/1/1 to 2016/4/1`, resolving smart dates
+/// relative to `day`. The end of a span is the start of the date given after
+/// `to`, so `to 2016` ends before the year 2016 begins.
+pub fn parse_date_span(day: Date<Local>, text: &str) -> Result<DateSpan, String> {
+    let lower = text.trim().to_lowercase();
+    let words: Vec<&str> = lower.split_whitespace().collect();
+    let is_to = |w: &&str| *w == "to" || *w == "until";
+    let smart = |ws: &[&str]| -> Result<DateSpan, String> {
+        let s = ws.join(" ");
+        parse_smart_date(day, &s).ok_or(format!("could not parse date '{}'", s))
+    };
+
+    if words.is_empty() {
+        return Err(String::from("empty date"));
+    }
+    let (from, rest) = match words[0] {
+        "from" | "since" => (true, &words[1..]),
+        "in" => return smart(&words[1..]),
+        _ => (false, &words[..])
+    };
+    match rest.iter().position(is_to) {
+        Some(i) => {
+            let start = if i == 0 { None } else { try!(smart(&rest[..i])).start };
+            let end = try!(smart(&rest[i + 1..])).start;
+            Ok(DateSpan::new(start, end))
+        },
+        None if from => Ok(DateSpan::new(try!(smart(rest)).start, None)),
+        None => smart(rest)
+    }
+}
+
+/// How often a periodic transaction recurs
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Interval {
+    /// Every given number of periods
+    Every(u32, Period),
+    /// On the given day of every month, or the month's last day if it is shorter
+    DayOfMonth(u32),
+    /// On the given day of every week, Monday being 1
+    DayOfWeek(u32)
+}
+
+/// A parsed period expression such as `every 2 weeks from 2016/01/04`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PeriodExpression {
+    pub interval: Interval,
+    pub span: DateSpan
+}
+
+impl PeriodExpression {
+    /// The dates on which the expression occurs within `span`. Intervals of
+    /// whole periods count from the start of the expression's span, or else
+    /// from the start of the period containing the start of `span`. Fails if
+    /// the two spans together don't bound both ends.
+    pub fn occurrences(&self, span: &DateSpan) -> Result<Vec<Date<Local>>, String> {
+        let (start, end) = match self.span.intersect(span) {
+            DateSpan { start: Some(s), end: Some(e) } => (s, e),
+            _ => return Err(String::from("periodic transactions need a date span with a start and an end"))
+        };
+        let mut r = Vec::new();
+        match self.interval {
+            Interval::Every(n, p) => {
+                let anchor = self.span.start.unwrap_or(p.start_of(start));
+                let mut k = 0;
+                while p.add(anchor, k * n as i64) < end {
+                    r.push(p.add(anchor, k * n as i64));
+                    k += 1;
+                }
+            },
+            Interval::DayOfMonth(d) => {
+                let mut month = Period::Month.start_of(start);
+                while month < end {
+                    r.push((1..d + 1).rev().filter_map(|x| Local.ymd_opt(month.year(), month.month(), x).single()).next().unwrap());
+                    month = add_months(month, 1);
+                }
+            },
+            Interval::DayOfWeek(d) => {
+                let mut day = Period::Week.start_of(start) + Duration::days(d as i64 - 1);
+                while day < end {
+                    r.push(day);
+                    day = day + Duration::weeks(1);
+                }
+            }
+        }
+        Ok(r.into_iter().filter(|d| start <= *d && *d < end).collect())
+    }
+}
+
+/// Parses an ordinal such as `15th` or `2nd`
+fn parse_ordinal(s: &str) -> Option<u32> {
+    let digits = s.trim_right_matches(|c: char| c.is_alphabetic());
+    if ["st", "nd", "rd", "th"].contains(&&s[digits.len()..]) {
+        digits.parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Parses a period expression: an interval such as `daily`, `weekly`,
+/// `biweekly`, `monthly`, `quarterly`, `yearly`, `every 2 weeks`, `every
+/// month` or `every 15th day of month`, optionally followed by a date span
+/// as accepted by `parse_date_span`, e.g. `monthly in 2016`.
+pub fn parse_period_expression(day: Date<Local>, text: &str) -> Result<PeriodExpression, String> {
+    let lower = text.trim().to_lowercase();
+    let words: Vec<&str> = lower.split_whitespace().collect();
+    let err = || format!("could not parse period expression '{}'", text.trim());
+
+    let (interval, used) = if words.first() == Some(&"every") {
+        if words.len() >= 5 && words[2] == "day" && words[3] == "of" {
+            let n = try!(parse_ordinal(words[1]).ok_or(err()));
+            match words[4] {
+                "month" if n >= 1 && n <= 31 => (Interval::DayOfMonth(n), 5),
+                "week" if n >= 1 && n <= 7 => (Interval::DayOfWeek(n), 5),
+                _ => return Err(err())
+            }
+        } else {
+            let (n, i) = match words.get(1).and_then(|w| w.parse::<u32>().ok()) {
+                Some(n) if n > 0 => (n, 2),
+                Some(_) => return Err(err()),
+                None => (1, 1)
+            };
+            let period = try!(words.get(i).and_then(|w| Period::from_str(w.trim_right_matches('s'))).ok_or(err()));
+            (Interval::Every(n, period), i + 1)
+        }
+    } else {
+        let interval = match words.first() {
+            Some(&"daily") => Interval::Every(1, Period::Day),
+            Some(&"weekly") => Interval::Every(1, Period::Week),
+            Some(&"biweekly") | Some(&"fortnightly") => Interval::Every(2, Period::Week),
+            Some(&"monthly") => Interval::Every(1, Period::Month),
+            Some(&"bimonthly") => Interval::Every(2, Period::Month),
+            Some(&"quarterly") => Interval::Every(1, Period::Quarter),
+            Some(&"yearly") | Some(&"annually") => Interval::Every(1, Period::Year),
+            _ => return Err(err())
+        };
+        (interval, 1)
+    };
+
+    let span = if words.len() > used {
+        try!(parse_date_span(day, &words[used..].join(" ")))
+    } else {
+        DateSpan::everything()
+    };
+    Ok(PeriodExpression { interval: interval, span: span })
+}
+
+#[test]
+fn parse_date_span_test() {
+    let day = Local.ymd(2016, 3, 15);
+    assert_eq!(parse_date_span(day, "2016"), Ok(DateSpan::new(Some(Local.ymd(2016, 1, 1)), Some(Local.ymd(2017, 1, 1)))));
+    assert_eq!(parse_date_span(day, "2016-03"), Ok(DateSpan::new(Some(Local.ymd(2016, 3, 1)), Some(Local.ymd(2016, 4, 1)))));
+    assert_eq!(parse_date_span(day, "last month"), Ok(DateSpan::new(Some(Local.ymd(2016, 2, 1)), Some(Local.ymd(2016, 3, 1)))));
+    assert_eq!(parse_date_span(day, "this quarter"), Ok(DateSpan::new(Some(Local.ymd(2016, 1, 1)), Some(Local.ymd(2016, 4, 1)))));
+    assert_eq!(parse_date_span(day, "from 2016/1/1 to 2016/4/1"), Ok(DateSpan::new(Some(Local.ymd(2016, 1, 1)), Some(Local.ymd(2016, 4, 1)))));
+    assert_eq!(parse_date_span(day, "since yesterday"), Ok(DateSpan::new(Some(Local.ymd(2016, 3, 14)), None)));
+    assert_eq!(parse_date_span(day, "to 2015"), Ok(DateSpan::new(None, Some(Local.ymd(2015, 1, 1)))));
+    assert_eq!(parse_date_span(day, "3/5"), Ok(DateSpan::day(Local.ymd(2016, 3, 5))));
+    assert!(parse_date_span(day, "whenever").is_err());
+    assert!(parse_date_span(day, "2016/13").is_err());
+}
+
+#[test]
+fn add_months_test() {
+    assert_eq!(Period::Month.add(Local.ymd(2016, 1, 31), 1), Local.ymd(2016, 2, 29));
+    assert_eq!(Period::Month.add(Local.ymd(2016, 1, 15), -1), Local.ymd(2015, 12, 15));
+    assert!(DateSpan::new(None, Some(Local.ymd(2016, 1, 1))).contains(&Local.ymd(2015, 12, 31)));
+    assert!(!DateSpan::day(Local.ymd(2016, 1, 1)).contains(&Local.ymd(2016, 1, 2)));
+}
+
+#[test]
+fn period_expression_test() {
+    let day = Local.ymd(2016, 3, 15);
+    let dates = |text: &str, span: DateSpan| parse_period_expression(day, text).unwrap().occurrences(&span).unwrap();
+    let q1 = DateSpan::new(Some(Local.ymd(2016, 1, 1)), Some(Local.ymd(2016, 4, 1)));
+
+    assert_eq!(dates("monthly", q1.clone()), vec!(Local.ymd(2016, 1, 1), Local.ymd(2016, 2, 1), Local.ymd(2016, 3, 1)));
+    assert_eq!(dates("every 15th day of month", q1.clone()), vec!(Local.ymd(2016, 1, 15), Local.ymd(2016, 2, 15), Local.ymd(2016, 3, 15)));
+    assert_eq!(dates("every 31st day of month", q1.clone())[1], Local.ymd(2016, 2, 29));
+    assert_eq!(dates("every 2 weeks from 2016/01/04", q1.clone()).len(), 7);
+    assert_eq!(dates("every 2 weeks from 2016/01/04", q1.clone())[1], Local.ymd(2016, 1, 18));
+    assert_eq!(dates("every 3rd day of week", q1.clone())[0], Local.ymd(2016, 1, 6));
+    assert_eq!(dates("quarterly in 2016", DateSpan::everything()).len(), 4);
+    assert_eq!(dates("monthly from 2016/02/10 to 2016/05/01", DateSpan::everything()), vec!(Local.ymd(2016, 2, 10), Local.ymd(2016, 3, 10), Local.ymd(2016, 4, 10)));
+
+    assert!(parse_period_expression(day, "monthly").unwrap().occurrences(&DateSpan::everything()).is_err());
+    assert!(parse_period_expression(day, "every 0 days").is_err());
+    assert!(parse_period_expression(day, "every 32nd day of month").is_err());
+    assert!(parse_period_expression(day, "sometimes").is_err());
+}
@@ -1,13 +1,21 @@
 use std::ops;
+use std::cmp;
 use std::cmp::Ordering;
 use std::fmt;
+use std::hash;
 use std::str;
 use std::error;
 
 #[derive(Debug, Clone, PartialEq)]
-enum DecimalErrorKind {
+pub enum DecimalErrorKind {
     Empty,
+    NoDigits,
     InvalidChar(char, u32),
+    MultipleDecimalMarks(u32),
+    MisplacedSign(u32),
+    MisplacedGroupSeparator(u32),
+    InvalidExponent(u32),
+    Overflow,
 }
 
 impl DecimalErrorKind {
@@ -15,22 +23,97 @@ impl DecimalErrorKind {
         match *self {
             DecimalErrorKind::Empty =>
                 "cannot parse decimal from empty string".to_string(),
+            DecimalErrorKind::NoDigits =>
+                "decimal has no digits".to_string(),
             DecimalErrorKind::InvalidChar(c, i) => {
                 format!("invalid character '{}' found at index {}", c, i)
-            }
+            },
+            DecimalErrorKind::MultipleDecimalMarks(i) =>
+                format!("second decimal mark found at index {}", i),
+            DecimalErrorKind::MisplacedSign(i) =>
+                format!("sign found at index {}, expected only at the start", i),
+            DecimalErrorKind::MisplacedGroupSeparator(i) =>
+                format!("digit group separator at index {} is not between digits of the integer part", i),
+            DecimalErrorKind::InvalidExponent(i) =>
+                format!("invalid exponent starting at index {}", i),
+            DecimalErrorKind::Overflow =>
+                "decimal is too large".to_string(),
         }
 
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Ord, Hash)]
+/// The notation `Decimal::parse_with` accepts
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecimalFormat {
+    pub decimal_mark: char,
+    pub group_separator: Option<char>,
+    pub allow_exponent: bool
+}
+
+impl DecimalFormat {
+    /// A `.` decimal mark, no digit groups and optional exponents, as in `-1.5e3`
+    pub fn new() -> DecimalFormat {
+        DecimalFormat {
+            decimal_mark: '.',
+            group_separator: None,
+            allow_exponent: true
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArithmeticErrorKind {
+    Overflow,
+    DivisionByZero,
+}
+
+impl ArithmeticErrorKind {
+    fn desc(&self) -> String {
+        match *self {
+            ArithmeticErrorKind::Overflow => "decimal arithmetic overflowed".to_string(),
+            ArithmeticErrorKind::DivisionByZero => "decimal division by zero".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArithmeticError {
+    pub kind: ArithmeticErrorKind,
+    desc: String,
+}
+
+impl ArithmeticError {
+    fn new(kind: ArithmeticErrorKind) -> Self {
+        let desc = kind.desc();
+        ArithmeticError { kind: kind, desc: desc }
+    }
+}
+
+impl fmt::Display for ArithmeticError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.desc.fmt(f)
+    }
+}
+
+impl error::Error for ArithmeticError {
+    fn description(&self) -> &str {
+        &self.desc
+    }
+}
+
+/// Number of places to the right of the decimal kept by the `/` operator
+/// beyond those of its operands
+pub const DIV_EXTRA_SCALE: u32 = 10;
+
+#[derive(Debug, Clone, Copy)]
 pub struct Decimal {
-    pub unscaled: i64,
+    pub unscaled: i128,
     pub scale: u32
 }
 
 impl Decimal {
-    pub fn new(unscaled: i64, scale: u32) -> Decimal {
+    pub fn new(unscaled: i128, scale: u32) -> Decimal {
         Decimal { unscaled: unscaled, scale: scale }
     }
 
@@ -39,28 +122,343 @@ impl Decimal {
     /// # Examples
     /// ```
     /// # use decimal::Decimal;
-    /// assert_eq!(Decimal::new(1, 0).adjust_scale(2), Decimal::new(100, 2));
-    /// assert_eq!(Decimal::new(10, 1).adjust_scale(2), Decimal::new(100, 2));
-    /// assert_eq!(Decimal::new(1000, 3).adjust_scale(2), Decimal::new(100, 2));
+    /// assert!(Decimal::new(1, 0).adjust_scale(2).identical(&Decimal::new(100, 2)));
+    /// assert!(Decimal::new(10, 1).adjust_scale(2).identical(&Decimal::new(100, 2)));
+    /// assert!(Decimal::new(1000, 3).adjust_scale(2).identical(&Decimal::new(100, 2)));
     /// assert_eq!(Decimal::new(125, 2).adjust_scale(1), Decimal::new(12, 1));
     /// ```
     pub fn adjust_scale(&self, new_scale: u32) -> Decimal {
+        or_panic(self.checked_adjust_scale(new_scale))
+    }
+
+    /// Like `adjust_scale`, but fails if adding places overflows.
+    ///
+    /// # Examples
+    /// ```
+    /// # use decimal::Decimal;
+    /// assert!(Decimal::new(1, 0).checked_adjust_scale(39).is_err());
+    /// assert!(Decimal::new(0, 0).checked_adjust_scale(4000000000).is_ok());
+    /// assert_eq!(Decimal::new(1, 0).checked_adjust_scale(38).unwrap().checked_adjust_scale(0), Ok(Decimal::new(1, 0)));
+    /// ```
+    pub fn checked_adjust_scale(&self, new_scale: u32) -> Result<Decimal, ArithmeticError> {
         match self.scale.cmp(&new_scale) {
-            Ordering::Equal => self.clone(),
-            Ordering::Greater => Decimal::new(downscale(&self.unscaled, self.scale - new_scale), new_scale),
-            Ordering::Less => Decimal::new(upscale(&self.unscaled, new_scale - self.scale), new_scale)
+            Ordering::Equal => Ok(self.clone()),
+            Ordering::Greater => Ok(Decimal::new(downscale(&self.unscaled, self.scale - new_scale), new_scale)),
+            Ordering::Less => upscale(&self.unscaled, new_scale - self.scale).map(|n| Decimal::new(n, new_scale))
         }
     }
 
     pub fn abs(self) -> Decimal {
         Decimal { unscaled: self.unscaled.abs(), scale: self.scale }
     }
+
+    /// Removes trailing zeros after the decimal point.
+    ///
+    /// # Examples
+    /// ```
+    /// # use decimal::Decimal;
+    /// assert!(Decimal::new(1500, 3).normalize().identical(&Decimal::new(15, 1)));
+    /// ```
+    pub fn normalize(&self) -> Decimal {
+        self.normalize_to(0)
+    }
+
+    /// Removes trailing zeros after the decimal point, keeping at least `min_scale` places.
+    fn normalize_to(&self, min_scale: u32) -> Decimal {
+        if self.unscaled == 0 {
+            return Decimal::new(0, cmp::min(self.scale, min_scale));
+        }
+        let mut r = self.clone();
+        while r.scale > min_scale && r.unscaled % 10 == 0 {
+            r = Decimal::new(r.unscaled / 10, r.scale - 1);
+        }
+        r
+    }
+
+    /// Divides by `other`, keeping `scale` places to the right of the decimal.
+    ///
+    /// # Examples
+    /// ```
+    /// # use decimal::Decimal;
+    /// assert!(Decimal::new(1, 0).div_with_scale(&Decimal::new(125, 2), 2).identical(&Decimal::new(80, 2)));
+    /// assert_eq!(Decimal::new(2, 0).div_with_scale(&Decimal::new(3, 0), 3), Decimal::new(666, 3));
+    /// ```
+    pub fn div_with_scale(&self, other: &Decimal, scale: u32) -> Decimal {
+        or_panic(self.checked_div(other, scale))
+    }
+
+    /// Divides by `other`, truncating the quotient to `scale` places to the
+    /// right of the decimal.
+    ///
+    /// # Examples
+    /// ```
+    /// # use decimal::{ ArithmeticErrorKind, Decimal };
+    /// assert_eq!(Decimal::new(10, 0).checked_div(&Decimal::new(4, 0), 2), Ok(Decimal::new(250, 2)));
+    /// assert_eq!(Decimal::new(1, 0).checked_div(&Decimal::new(0, 2), 2).unwrap_err().kind, ArithmeticErrorKind::DivisionByZero);
+    /// ```
+    pub fn checked_div(&self, other: &Decimal, scale: u32) -> Result<Decimal, ArithmeticError> {
+        if other.unscaled == 0 {
+            return Err(ArithmeticError::new(ArithmeticErrorKind::DivisionByZero));
+        }
+        let shift = scale as i64 + other.scale as i64 - self.scale as i64;
+        let n = if shift >= 0 {
+            try!(upscale(&self.unscaled, shift as u32))
+        } else {
+            downscale(&self.unscaled, (-shift) as u32)
+        };
+        n.checked_div(other.unscaled).map(|q| Decimal::new(q, scale)).ok_or(overflow())
+    }
+
+    /// # Examples
+    /// ```
+    /// # use decimal::Decimal;
+    /// assert_eq!(Decimal::new(15, 1).checked_add(Decimal::new(25, 2)), Ok(Decimal::new(175, 2)));
+    /// assert!(Decimal::new(1, 0).checked_add(Decimal::new(::std::i128::MAX, 0)).is_err());
+    /// ```
+    pub fn checked_add(self, other: Decimal) -> Result<Decimal, ArithmeticError> {
+        let (a, b) = try!(align(self, other));
+        a.unscaled.checked_add(b.unscaled).map(|n| Decimal::new(n, a.scale)).ok_or(overflow())
+    }
+
+    pub fn checked_sub(self, other: Decimal) -> Result<Decimal, ArithmeticError> {
+        let (a, b) = try!(align(self, other));
+        a.unscaled.checked_sub(b.unscaled).map(|n| Decimal::new(n, a.scale)).ok_or(overflow())
+    }
+
+    /// Multiplies exactly. If the product doesn't fit, trailing zeros of the
+    /// operands are dropped and the multiplication retried before failing.
+    ///
+    /// # Examples
+    /// ```
+    /// # use decimal::Decimal;
+    /// let satoshis = Decimal::new(123456789, 8);
+    /// assert_eq!(satoshis.checked_mul(satoshis), Ok(Decimal::new(15241578750190521, 16)));
+    /// assert!(Decimal::new(::std::i128::MAX, 0).checked_mul(Decimal::new(2, 0)).is_err());
+    /// ```
+    pub fn checked_mul(self, other: Decimal) -> Result<Decimal, ArithmeticError> {
+        let scale = try!(self.scale.checked_add(other.scale).ok_or(overflow()));
+        match self.unscaled.checked_mul(other.unscaled) {
+            Some(n) => Ok(Decimal::new(n, scale)),
+            None => {
+                let (a, b) = (self.normalize(), other.normalize());
+                a.unscaled.checked_mul(b.unscaled).map(|n| Decimal::new(n, a.scale + b.scale)).ok_or(overflow())
+            }
+        }
+    }
+
+    pub fn checked_rem(self, other: Decimal) -> Result<Decimal, ArithmeticError> {
+        if other.unscaled == 0 {
+            return Err(ArithmeticError::new(ArithmeticErrorKind::DivisionByZero));
+        }
+        let (a, b) = try!(align(self, other));
+        a.unscaled.checked_rem(b.unscaled).map(|n| Decimal::new(n, a.scale)).ok_or(overflow())
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.unscaled == 0
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.unscaled < 0
+    }
+
+    /// Whether both the value and the scale are the same, unlike `==`
+    /// which ignores the scale.
+    pub fn identical(&self, other: &Decimal) -> bool {
+        self.unscaled == other.unscaled && self.scale == other.scale
+    }
+
+    /// Parses a decimal written in the given format: an optional `+` or `-`,
+    /// digits with at most one decimal mark, digit group separators between
+    /// the digits of the integer part and, if allowed, an exponent such as
+    /// `e-3`. Error positions are character indices.
+    ///
+    /// # Examples
+    /// ```
+    /// # use decimal::{ Decimal, DecimalErrorKind, DecimalFormat };
+    /// let mut format = DecimalFormat::new();
+    /// format.decimal_mark = ',';
+    /// format.group_separator = Some('.');
+    /// assert_eq!(Decimal::parse_with("-1.234.567,89", &format), Ok(Decimal::new(-123456789, 2)));
+    /// assert_eq!(Decimal::parse_with("1..234", &format).unwrap_err().kind, DecimalErrorKind::MisplacedGroupSeparator(1));
+    /// assert_eq!(Decimal::parse_with("1,2,3", &format).unwrap_err().kind, DecimalErrorKind::MultipleDecimalMarks(3));
+    ///
+    /// format.allow_exponent = true;
+    /// assert_eq!(Decimal::parse_with("1e-100", &format).unwrap_err().kind, DecimalErrorKind::Overflow);
+    /// assert_eq!(Decimal::parse_with("1e100", &format).unwrap_err().kind, DecimalErrorKind::Overflow);
+    /// assert_eq!(Decimal::parse_with("1e-38", &format), Ok(Decimal::new(1, 38)));
+    /// ```
+    pub fn parse_with(s: &str, format: &DecimalFormat) -> Result<Decimal, ParseDecimalError> {
+        let err = |kind| Err(ParseDecimalError::new(kind));
+        let chars: Vec<char> = s.chars().collect();
+        if chars.is_empty() {
+            return err(DecimalErrorKind::Empty);
+        }
+        let mut unscaled: i128 = 0;
+        let mut scale: i64 = 0;
+        let mut negative = false;
+        let mut seen_digit = false;
+        let mut seen_decimal = false;
+        let mut exponent = None;
+        for (index, &c) in chars.iter().enumerate() {
+            let i = index as u32;
+            let is_digit_at = |j: usize| chars.get(j).map_or(false, |c| c.is_digit(10));
+            match c {
+                '+' | '-' if index == 0 => negative = c == '-',
+                '+' | '-' => return err(DecimalErrorKind::MisplacedSign(i)),
+                c if c == format.decimal_mark => {
+                    if seen_decimal {
+                        return err(DecimalErrorKind::MultipleDecimalMarks(i));
+                    }
+                    seen_decimal = true;
+                },
+                c if Some(c) == format.group_separator => {
+                    if seen_decimal || index == 0 || !is_digit_at(index - 1) || !is_digit_at(index + 1) {
+                        return err(DecimalErrorKind::MisplacedGroupSeparator(i));
+                    }
+                },
+                'e' | 'E' if format.allow_exponent => {
+                    if !seen_digit {
+                        return err(DecimalErrorKind::InvalidExponent(i));
+                    }
+                    exponent = Some(index);
+                    break;
+                },
+                c if c.is_digit(10) => {
+                    seen_digit = true;
+                    unscaled = match unscaled.checked_mul(10).and_then(|n| n.checked_add(c.to_digit(10).unwrap() as i128)) {
+                        Some(n) => n,
+                        None => return err(DecimalErrorKind::Overflow)
+                    };
+                    if seen_decimal {
+                        scale += 1;
+                    }
+                },
+                c => return err(DecimalErrorKind::InvalidChar(c, i))
+            }
+        }
+        if !seen_digit {
+            return err(DecimalErrorKind::NoDigits);
+        }
+        if let Some(e) = exponent {
+            let text: String = chars[e + 1..].iter().cloned().collect();
+            let digits = text.trim_left_matches(|c| c == '+' || c == '-');
+            if digits.is_empty() || !digits.chars().all(|c| c.is_digit(10)) || text.len() - digits.len() > 1 {
+                return err(DecimalErrorKind::InvalidExponent(e as u32));
+            }
+            match text.parse::<i64>() {
+                Ok(x) if x.abs() <= ::std::u32::MAX as i64 => scale -= x,
+                _ => return err(DecimalErrorKind::Overflow)
+            }
+        }
+        if scale < 0 {
+            unscaled = match upscale(&unscaled, (-scale) as u32) {
+                Ok(n) => n,
+                Err(_) => return err(DecimalErrorKind::Overflow)
+            };
+            scale = 0;
+        }
+        if scale > MAX_SHIFT as i64 {
+            return err(DecimalErrorKind::Overflow);
+        }
+        Ok(Decimal::new(if negative { -unscaled } else { unscaled }, scale as u32))
+    }
+
+    /// Rounds to `new_scale` places to the right of the decimal, or adds
+    /// places if there are fewer.
+    ///
+    /// # Examples
+    /// ```
+    /// # use decimal::{ Decimal, Rounding };
+    /// assert_eq!(Decimal::new(125, 2).round(1, Rounding::HalfEven), Decimal::new(12, 1));
+    /// assert_eq!(Decimal::new(135, 2).round(1, Rounding::HalfEven), Decimal::new(14, 1));
+    /// assert_eq!(Decimal::new(-125, 2).round(1, Rounding::HalfUp), Decimal::new(-13, 1));
+    /// assert_eq!(Decimal::new(-121, 2).round(1, Rounding::Floor), Decimal::new(-13, 1));
+    /// assert_eq!(Decimal::new(121, 2).round(1, Rounding::Ceiling), Decimal::new(13, 1));
+    /// assert_eq!(Decimal::new(129, 2).round(1, Rounding::Truncate), Decimal::new(12, 1));
+    /// assert!(Decimal::new(5, 1).round(2, Rounding::Floor).identical(&Decimal::new(50, 2)));
+    /// ```
+    pub fn round(&self, new_scale: u32, mode: Rounding) -> Decimal {
+        if new_scale >= self.scale {
+            return self.adjust_scale(new_scale);
+        }
+        let n = self.unscaled;
+        let (q, r, d) = match upscale(&1, self.scale - new_scale) {
+            Ok(d) => (n / d, n % d, Some(d)),
+            Err(_) => (0, n, None)
+        };
+        if r == 0 {
+            return Decimal::new(q, new_scale);
+        }
+        // compares the discarded part against half a unit of the last kept place
+        let half = match d {
+            Some(d) => r.abs().cmp(&(d - r.abs())),
+            None => Ordering::Less
+        };
+        let away = match mode {
+            Rounding::Truncate => false,
+            Rounding::Floor => n < 0,
+            Rounding::Ceiling => n > 0,
+            Rounding::HalfUp => half != Ordering::Less,
+            Rounding::HalfEven => half == Ordering::Greater || (half == Ordering::Equal && q % 2 != 0)
+        };
+        Decimal::new(if !away { q } else if n < 0 { q - 1 } else { q + 1 }, new_scale)
+    }
+}
+
+/// How `Decimal::round` treats the places it drops
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    /// To the nearest value, ties to the even neighbour
+    HalfEven,
+    /// To the nearest value, ties away from zero
+    HalfUp,
+    /// Toward negative infinity
+    Floor,
+    /// Toward positive infinity
+    Ceiling,
+    /// Toward zero
+    Truncate
+}
+
+/// Parses a rounding mode as given on the command line, e.g. `half-even`
+impl str::FromStr for Rounding {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Rounding, String> {
+        match s.trim() {
+            "half-even" => Ok(Rounding::HalfEven),
+            "half-up" => Ok(Rounding::HalfUp),
+            "floor" => Ok(Rounding::Floor),
+            "ceiling" => Ok(Rounding::Ceiling),
+            "truncate" => Ok(Rounding::Truncate),
+            m => Err(format!("unknown rounding '{}', expected half-even, half-up, floor, ceiling or truncate", m))
+        }
+    }
+}
+
+fn overflow() -> ArithmeticError {
+    ArithmeticError::new(ArithmeticErrorKind::Overflow)
+}
+
+/// Brings two decimals to the larger of their scales
+fn align(a: Decimal, b: Decimal) -> Result<(Decimal, Decimal), ArithmeticError> {
+    match a.scale.cmp(&b.scale) {
+        Ordering::Equal => Ok((a, b)),
+        Ordering::Less => Ok((try!(a.checked_adjust_scale(b.scale)), b)),
+        Ordering::Greater => Ok((a, try!(b.checked_adjust_scale(a.scale))))
+    }
+}
+
+/// The operators panic on overflow, like those of the primitive integers
+fn or_panic(r: Result<Decimal, ArithmeticError>) -> Decimal {
+    match r {
+        Ok(d) => d,
+        Err(e) => panic!("{}", e)
+    }
 }
 
-/// `Decimal` is only `PartialOrd`, not `Ord`, because its ordering is not antisymmetric,
-/// i.e., two decimals may compare `Ordering::Equal` but not be `==` to one another due
-/// to differing scales. However note that all `Decimal`s are comparable, so
-/// `partial_cmp` will never return `None`.
+/// Decimals compare by value regardless of scale, so `1.0 == 1.00`. Use
+/// `identical` to also compare the scale.
 ///
 /// # Examples
 /// ```
@@ -71,18 +469,54 @@ impl Decimal {
 /// let two_tenths = Decimal::new(2, 1);
 /// assert!(one > two_tenths);
 /// let one_point_oh = Decimal::new(10, 1);
-/// assert_eq!(::std::cmp::Ordering::Equal, one.partial_cmp(&one_point_oh).unwrap());
+/// assert_eq!(one, one_point_oh);
+/// assert!(!one.identical(&one_point_oh));
 /// ```
+impl Ord for Decimal {
+    fn cmp(&self, other: &Decimal) -> Ordering {
+        match align(*self, *other) {
+            Ok((a, b)) => a.unscaled.cmp(&b.unscaled),
+            // the value that overflowed when scaled up has the larger magnitude
+            Err(_) if self.scale < other.scale => if self.is_negative() { Ordering::Less } else { Ordering::Greater },
+            Err(_) => if other.is_negative() { Ordering::Greater } else { Ordering::Less }
+        }
+    }
+}
+
 impl PartialOrd for Decimal {
     fn partial_cmp(&self, other: &Decimal) -> Option<Ordering> {
-        Some(match self.scale.cmp(&other.scale) {
-            Ordering::Equal => self.unscaled.cmp(&other.unscaled),
-            Ordering::Greater => self.unscaled.cmp(&upscale(&other.unscaled, self.scale - other.scale)),
-            _ => upscale(&self.unscaled, other.scale - self.scale).cmp(&other.unscaled)
-        })
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Decimal) -> bool {
+        self.cmp(other) == Ordering::Equal
     }
 }
 
+impl Eq for Decimal {}
+
+/// Hashes the normalized value, so equal decimals hash alike whatever their scale.
+///
+/// # Examples
+/// ```
+/// # use decimal::Decimal;
+/// use std::collections::HashSet;
+/// let mut set = HashSet::new();
+/// set.insert(Decimal::new(150, 2));
+/// assert!(set.contains(&Decimal::new(15, 1)));
+/// ```
+impl hash::Hash for Decimal {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        let n = self.normalize();
+        n.unscaled.hash(state);
+        n.scale.hash(state);
+    }
+}
+
+/// Parses with the default `DecimalFormat`.
+///
 /// # Examples
 ///
 /// Using `FromStr` directly:
@@ -96,35 +530,16 @@ impl PartialOrd for Decimal {
 ///
 /// ```
 /// # use decimal::Decimal;
-/// assert_eq!(Decimal::new(-100, 2), "-1.00".parse().unwrap());
+/// assert!(Decimal::new(-100, 2).identical(&"-1.00".parse().unwrap()));
+/// assert_eq!(Decimal::new(15, 0), "+1.5e1".parse().unwrap());
+/// assert_eq!(Decimal::new(25, 4), "2.5E-3".parse().unwrap());
+/// assert!(".".parse::<Decimal>().is_err());
+/// assert!("1-2".parse::<Decimal>().is_err());
 /// ```
 impl str::FromStr for Decimal {
     type Err = ParseDecimalError;
     fn from_str(s: &str) -> Result<Decimal, ParseDecimalError> {
-        let mut unscaled: i64 = 0;
-        let mut scale: u32 = 0;
-        let mut index: u32 = 0;
-        let mut negative = false;
-        let mut seen_decimal = false;
-        for c in s.chars() {
-            match c {
-                '-' if index == 0 => negative = true,
-                '.' => seen_decimal = true,
-                c if c.is_digit(10) => {
-                    unscaled = (unscaled * 10) + c.to_digit(10).unwrap() as i64;
-                    if seen_decimal {
-                        scale += 1;
-                    }
-                },
-                c => return Err(ParseDecimalError::new(DecimalErrorKind::InvalidChar(c, index)))
-            }
-            index += 1;
-        }
-        if index == 0 {
-            Err(ParseDecimalError::new(DecimalErrorKind::Empty))
-        } else {
-            Ok(Decimal::new(if negative { -unscaled } else { unscaled }, scale))
-        }
+        Decimal::parse_with(s, &DecimalFormat::new())
     }
 }
 
@@ -158,36 +573,35 @@ impl fmt::Display for Decimal {
 impl ops::Add for Decimal {
     type Output = Decimal;
     fn add(self, other: Decimal) -> Decimal {
-        match self.scale.cmp(&other.scale) {
-            Ordering::Equal => Decimal::new(self.unscaled + other.unscaled, self.scale),
-            Ordering::Less => self.adjust_scale(other.scale) + other,
-            Ordering::Greater => self + other.adjust_scale(self.scale)
-        }
+        or_panic(self.checked_add(other))
     }
 }
 
 impl ops::Sub for Decimal {
     type Output = Decimal;
     fn sub(self, other: Decimal) -> Decimal {
-        match self.scale.cmp(&other.scale) {
-            Ordering::Equal => Decimal::new(self.unscaled - other.unscaled, self.scale),
-            Ordering::Less => self.adjust_scale(other.scale) - other,
-            Ordering::Greater => self - other.adjust_scale(self.scale)
-        }
+        or_panic(self.checked_sub(other))
+    }
+}
+
+impl ops::Neg for Decimal {
+    type Output = Decimal;
+    fn neg(self) -> Decimal {
+        Decimal::new(-self.unscaled, self.scale)
     }
 }
 
 impl ops::Mul for Decimal {
     type Output = Decimal;
     fn mul(self, other: Decimal) -> Decimal {
-        Decimal::new(self.unscaled * other.unscaled, self.scale + other.scale)
+        or_panic(self.checked_mul(other))
     }
 }
 
 impl ops::Mul<i64> for Decimal {
     type Output = Decimal;
     fn mul(self, i: i64) -> Decimal {
-        Decimal::new(self.unscaled * i, self.scale)
+        self * Decimal::new(i as i128, 0)
     }
 }
 
@@ -196,53 +610,59 @@ impl ops::Mul<i64> for Decimal {
 impl ops::Mul<Decimal> for i64 {
     type Output = Decimal;
     fn mul(self, d: Decimal) -> Decimal {
-        Decimal::new(self * d.unscaled, d.scale)
+        d * self
     }
 }
 
+/// Keeps `DIV_EXTRA_SCALE` more places than the operands, truncating the
+/// rest, then drops trailing zeros down to the scale of the dividend.
+///
+/// # Examples
+/// ```
+/// # use decimal::Decimal;
+/// assert!((Decimal::new(1000, 2) / Decimal::new(4, 0)).identical(&Decimal::new(250, 2)));
+/// assert_eq!(Decimal::new(1, 0) / Decimal::new(3, 0), Decimal::new(3333333333, 10));
+/// ```
 impl ops::Div for Decimal {
     type Output = Decimal;
     fn div(self, other: Decimal) -> Decimal {
-        let s = if other.scale > self.scale {
-            self.adjust_scale(other.scale)
-        } else {
-            self
-        };
-        Decimal::new(s.unscaled / other.unscaled, s.scale - other.scale)
+        let scale = if self.scale > other.scale { self.scale } else { other.scale };
+        or_panic(self.checked_div(&other, scale + DIV_EXTRA_SCALE)).normalize_to(self.scale)
     }
 }
 
 impl ops::Rem for Decimal {
     type Output = Decimal;
     fn rem(self, other: Decimal) -> Decimal {
-        let s = if other.scale > self.scale {
-            self.adjust_scale(other.scale)
-        } else {
-            self
-        };
-        Decimal::new(s.unscaled % other.unscaled, s.scale)
+        or_panic(self.checked_rem(other))
     }
 }
 
-fn downscale(n: &i64, down_by: u32) -> i64 {
-    let mut result = n.clone();
-    for _ in 0..down_by {
-        result = result / 10;
+/// The most decimal places an `i128` can be shifted by, and so the largest
+/// scale a parsed decimal may have
+const MAX_SHIFT: u32 = 38;
+
+fn downscale(n: &i128, down_by: u32) -> i128 {
+    if down_by > MAX_SHIFT {
+        0
+    } else {
+        n / 10i128.pow(down_by)
     }
-    result
 }
 
-fn upscale(n: &i64, up_by: u32) -> i64 {
-    let mut result = n.clone();
-    for _ in 0..up_by {
-        result = result * 10;
+fn upscale(n: &i128, up_by: u32) -> Result<i128, ArithmeticError> {
+    if *n == 0 {
+        Ok(0)
+    } else if up_by > MAX_SHIFT {
+        Err(overflow())
+    } else {
+        n.checked_mul(10i128.pow(up_by)).ok_or(overflow())
     }
-    result
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct ParseDecimalError {
-    kind: DecimalErrorKind,
+    pub kind: DecimalErrorKind,
     desc: String,
 }
 
@@ -0,0 +1,202 @@
+use chrono::{ Date, Local };
+
+use account_name::AccountName;
+use amount::{ Amount, MixedAmount };
+use commodity::Commodity;
+use journal::{ AccountType, Journal };
+use lot::{ Disposal, Holding, LotBook, LotError, LotSelection };
+use price::PriceDb;
+use quantity::Quantity;
+
+/// A sale of units from one lot, as listed on a capital gains report
+#[derive(Clone, PartialEq, Eq)]
+pub struct RealisedGain {
+    pub account: AccountName,
+    pub commodity: Commodity,
+    pub quantity: Quantity,
+    pub acquired: Date<Local>,
+    pub disposed: Date<Local>,
+    pub holding_days: i64,
+    pub proceeds: Option<Amount>,
+    pub cost_basis: Amount,
+    pub gain: Option<Amount>
+}
+
+/// An open lot valued at the report date
+#[derive(Clone, PartialEq, Eq)]
+pub struct UnrealisedGain {
+    pub account: AccountName,
+    pub commodity: Commodity,
+    pub quantity: Quantity,
+    pub acquired: Date<Local>,
+    pub holding_days: i64,
+    pub market_value: Option<Amount>,
+    pub cost_basis: Amount,
+    pub gain: Option<Amount>
+}
+
+/// Realised gains from sales up to a date, and unrealised gains of the lots
+/// still held on that date
+#[derive(Clone, PartialEq, Eq)]
+pub struct GainsReport {
+    pub date: Date<Local>,
+    pub realised: Vec<RealisedGain>,
+    pub unrealised: Vec<UnrealisedGain>
+}
+
+impl GainsReport {
+    /// Books the lots of the journal's transactions up to and including
+    /// `date` and values the remaining holdings at the market prices in
+    /// effect on that date, in the commodity they were bought with. Holdings
+    /// without a market price are listed without a value or gain.
+    pub fn new(journal: &Journal, selection: LotSelection, date: Date<Local>) -> Result<GainsReport, LotError> {
+        let transactions = journal.transactions_in_date_order().into_iter().filter(|t| t.date <= date).collect();
+        let book = try!(LotBook::from_transactions(transactions, selection,
+                                                   &|a| journal.account_type(a) == Some(AccountType::Expense)));
+        let prices = journal.price_db();
+        let mut unrealised = Vec::new();
+        for (account, inventory) in book.inventories.iter() {
+            for h in inventory.holdings.iter() {
+                unrealised.push(unrealised_gain(account, h, &prices, date));
+            }
+        }
+        Ok(GainsReport {
+            date: date,
+            realised: book.disposals.iter().map(|d| realised_gain(d, &prices)).collect(),
+            unrealised: unrealised
+        })
+    }
+
+    pub fn total_realised(&self) -> MixedAmount {
+        self.realised.iter().filter_map(|g| g.gain.clone()).collect::<MixedAmount>().normalise()
+    }
+
+    pub fn total_unrealised(&self) -> MixedAmount {
+        self.unrealised.iter().filter_map(|g| g.gain.clone()).collect::<MixedAmount>().normalise()
+    }
+
+    /// Shows the report as comma separated values, one section for realised
+    /// and one for unrealised gains, each with a header row. Every amount is
+    /// followed by its currency; missing values are left empty.
+    pub fn to_csv(&self) -> String {
+        let mut lines = vec!(String::from("account,commodity,quantity,acquired,disposed,holding days,\
+                                           proceeds,proceeds currency,cost basis,cost basis currency,gain,gain currency"));
+        for g in self.realised.iter() {
+            lines.push(format!("{},{},{},{},{},{},{},{},{}",
+                               csv_field(g.account.as_str()), csv_field(g.commodity.symbol()), g.quantity.0,
+                               g.acquired.format("%Y-%m-%d"), g.disposed.format("%Y-%m-%d"), g.holding_days,
+                               csv_amount(g.proceeds.as_ref()), csv_amount(Some(&g.cost_basis)), csv_amount(g.gain.as_ref())));
+        }
+        lines.push(String::new());
+        lines.push(String::from("account,commodity,quantity,acquired,holding days,\
+                                 market value,market value currency,cost basis,cost basis currency,gain,gain currency"));
+        for g in self.unrealised.iter() {
+            lines.push(format!("{},{},{},{},{},{},{},{}",
+                               csv_field(g.account.as_str()), csv_field(g.commodity.symbol()), g.quantity.0,
+                               g.acquired.format("%Y-%m-%d"), g.holding_days,
+                               csv_amount(g.market_value.as_ref()), csv_amount(Some(&g.cost_basis)), csv_amount(g.gain.as_ref())));
+        }
+        lines.join("\n") + "\n"
+    }
+}
+
+/// A sale's gain. Proceeds in another commodity than the cost basis are
+/// valued in it at the market price on the day of the sale, if there is one.
+fn realised_gain(d: &Disposal, prices: &PriceDb) -> RealisedGain {
+    let gain = d.gain().or_else(|| d.proceeds.as_ref().and_then(|p| {
+        p.commodity.conversion_rate(&d.cost_basis.commodity, &d.disposed, prices).map(|rate| {
+            let mut r = d.cost_basis.clone();
+            r.quantity = Quantity((p.quantity.0 * rate).normalize()) - d.cost_basis.quantity.clone();
+            r
+        })
+    }));
+    RealisedGain {
+        account: d.account.clone(),
+        commodity: d.commodity.clone(),
+        quantity: d.quantity.clone(),
+        acquired: d.acquired,
+        disposed: d.disposed,
+        holding_days: (d.disposed - d.acquired).num_days(),
+        proceeds: d.proceeds.clone(),
+        cost_basis: d.cost_basis.clone(),
+        gain: gain
+    }
+}
+
+fn unrealised_gain(account: &AccountName, h: &Holding, prices: &PriceDb, date: Date<Local>) -> UnrealisedGain {
+    let cost_basis = h.cost_basis();
+    let market_value = h.commodity.conversion_rate(&cost_basis.commodity, &date, prices).map(|rate| {
+        let mut r = cost_basis.clone();
+        r.quantity = Quantity((h.quantity.0 * rate).normalize());
+        r
+    });
+    let gain = market_value.as_ref().map(|v| {
+        let mut r = v.clone();
+        r.quantity = v.quantity.clone() - cost_basis.quantity.clone();
+        r
+    });
+    UnrealisedGain {
+        account: account.clone(),
+        commodity: h.commodity.clone(),
+        quantity: h.quantity.clone(),
+        acquired: h.date,
+        holding_days: (date - h.date).num_days(),
+        market_value: market_value,
+        cost_basis: cost_basis,
+        gain: gain
+    }
+}
+
+/// An amount's quantity and currency as two fields, both empty if it is missing
+fn csv_amount(a: Option<&Amount>) -> String {
+    a.map_or(String::from(","), |x| format!("{},{}", x.quantity.0, csv_field(x.commodity.symbol())))
+}
+
+/// Quotes a field if it contains a comma or a quote
+fn csv_field(s: &str) -> String {
+    if s.contains(',') || s.contains('"') {
+        format!("\"{}\"", s.replace("\"", "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[test]
+fn gains_report_test() {
+    use chrono::TimeZone;
+    use decimal::Decimal;
+    use parser::parse_journal;
+    use journal::MarketPrice;
+
+    let mut journal = parse_journal("2015/03/01 buy\n    assets:broker  10 AAPL @ 100 USD\n    assets:cash\n\n\
+                                     2016/06/01 sell\n    assets:broker  -4 AAPL @ 130 USD\n    assets:cash\n\n\
+                                     2016/12/01 sell\n    assets:broker  -6 AAPL @ 90 USD\n    assets:cash\n").unwrap();
+    journal.balance_transactions().unwrap();
+    let mut price = Amount::new();
+    price.commodity = Commodity::new(String::from("USD"));
+    price.quantity = Quantity(Decimal::new(125, 0));
+    journal.add_market_price_mut(MarketPrice { date: Local.ymd(2016, 6, 30), commodity: Commodity::new(String::from("AAPL")), amount: price });
+
+    let report = GainsReport::new(&journal, LotSelection::Fifo, Local.ymd(2016, 7, 1)).unwrap();
+    assert!(report.realised.len() == 1);
+    assert!(report.realised[0].holding_days == 458);
+    assert!(report.realised[0].gain.as_ref().unwrap().quantity == Quantity(Decimal::new(120, 0)));
+    assert!(report.unrealised.len() == 1);
+    assert!(report.unrealised[0].market_value.as_ref().unwrap().quantity == Quantity(Decimal::new(750, 0)));
+    assert!(report.total_unrealised().0[0].quantity == Quantity(Decimal::new(150, 0)));
+    assert_eq!(report.to_csv().lines().nth(1), Some("assets:broker,AAPL,4,2015-03-01,2016-06-01,458,520,USD,400,USD,120,USD"));
+
+    let year_end = GainsReport::new(&journal, LotSelection::Fifo, Local.ymd(2016, 12, 31)).unwrap();
+    assert!(year_end.unrealised.is_empty());
+    assert!(year_end.total_realised().0[0].quantity == Quantity(Decimal::new(60, 0)));
+
+    let foreign = "2016/01/01 buy\n    assets:broker  10 AAPL @ 100 USD\n    assets:cash\n\n\
+                   2016/03/01 sell\n    assets:broker  -2 AAPL @ 100 EUR\n    assets:cash\n";
+    let sale = |journal: &str| {
+        let mut j = parse_journal(journal).unwrap();
+        j.balance_transactions().unwrap();
+        GainsReport::new(&j, LotSelection::Fifo, Local.ymd(2016, 12, 31)).unwrap().to_csv().lines().nth(1).unwrap().to_string()
+    };
+    assert_eq!(sale(foreign), "assets:broker,AAPL,2,2016-01-01,2016-03-01,60,200,EUR,200,USD,,");
+    assert_eq!(sale(&format!("P 2016/02/01 EUR 1.2 USD\n{}", foreign)), "assets:broker,AAPL,2,2016-01-01,2016-03-01,60,200,EUR,200,USD,40,USD");
+}
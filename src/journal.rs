@@ -1,8 +1,18 @@
-use transaction::{ Transaction, ModifierTransaction, PeriodicTransaction };
-use amount::Amount;
+use std::collections::{ HashMap, HashSet };
+use std::fmt;
+use std::error;
+use transaction::{ BalanceError, GenericSourcePos, Transaction, ModifierTransaction, PeriodicTransaction };
+use account_name::{ AccountAlias, AccountName };
+use amount::{ Amount, AmountStyle, MixedAmount };
 use commodity::Commodity;
+use dates::DateSpan;
+use price::PriceDb;
 use chrono::{ Date, Local };
-use posting::Posting;
+use posting::{ BalanceAssertion, Posting };
+use decimal::Decimal;
+use price::Price;
+use quantity::Quantity;
+use std::rc::Rc;
 
 #[derive(Clone, PartialEq, Eq)]
 pub struct MarketPrice {
@@ -11,18 +21,195 @@ pub struct MarketPrice {
     pub amount: Amount
 }
 
+/// The year given by a `Y` or `year` directive
 #[derive(Clone, PartialEq, Eq)]
-pub struct Year(usize);
+pub struct Year(pub i32);
 
+/// The type of an account, as declared with `type:` in an `account` directive
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AccountType {
+    Asset,
+    Liability,
+    Equity,
+    Revenue,
+    Expense,
+    Cash
+}
+
+impl AccountType {
+    /// Parses a type name or its one-letter code, e.g. `Asset` or `A`
+    pub fn from_str(s: &str) -> Option<AccountType> {
+        match s.trim().to_lowercase().as_ref() {
+            "a" | "asset" | "assets" => Some(AccountType::Asset),
+            "l" | "liability" | "liabilities" => Some(AccountType::Liability),
+            "e" | "equity" => Some(AccountType::Equity),
+            "r" | "revenue" | "revenues" => Some(AccountType::Revenue),
+            "x" | "expense" | "expenses" => Some(AccountType::Expense),
+            "c" | "cash" => Some(AccountType::Cash),
+            _ => None
+        }
+    }
+}
+
+/// An account declared with an `account` directive
+#[derive(Clone, PartialEq, Eq)]
+pub struct AccountDeclaration {
+    pub account: AccountName,
+    pub account_type: Option<AccountType>,
+    pub comment: String,
+    pub source_pos: GenericSourcePos
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BalanceAssertionError {
+    pub source_pos: GenericSourcePos,
+    pub account: AccountName,
+    pub expected: String,
+    pub actual: String,
+    desc: String,
+}
+
+impl BalanceAssertionError {
+    fn new(source_pos: GenericSourcePos, account: AccountName, expected: String, actual: String) -> Self {
+        let desc = format!("balance assertion failed for account {}: expected {}, but the balance is {}", account, expected, actual);
+        BalanceAssertionError { source_pos: source_pos, account: account, expected: expected, actual: actual, desc: desc }
+    }
+}
+
+impl fmt::Display for BalanceAssertionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.source_pos, self.desc)
+    }
+}
+
+impl error::Error for BalanceAssertionError {
+    fn description(&self) -> &str {
+        &self.desc
+    }
+}
+
+/// State set by directives that affects how the rest of a journal is read
 #[derive(Clone, PartialEq, Eq)]
 pub struct JournalContext {
-    year: Option<Year>,
+    pub year: Option<Year>,
+    /// The commodity and style of amounts written without a commodity
+    pub default_commodity: Option<Amount>,
+    /// Account prefixes from enclosing `apply account` directives, outermost first
+    pub parent_accounts: Vec<AccountName>,
+    /// Aliases declared so far, in order of declaration
+    pub aliases: Vec<AccountAlias>,
 }
 
 impl JournalContext {
     pub fn new() -> JournalContext {
         JournalContext {
-            year: None
+            year: None,
+            default_commodity: None,
+            parent_accounts: Vec::new(),
+            aliases: Vec::new()
+        }
+    }
+
+    /// Prefixes an account with the accounts of enclosing `apply account` directives
+    pub fn apply_parent_accounts(&self, account: AccountName) -> AccountName {
+        let mut components: Vec<&str> = self.parent_accounts.iter().map(|a| a.as_str()).collect();
+        components.push(account.as_str());
+        AccountName::from_components(&components)
+    }
+
+    /// The account a posting or declaration refers to: the written account
+    /// with the parent accounts applied, then rewritten by each alias, most
+    /// recently declared first
+    pub fn resolve_account(&self, account: AccountName) -> AccountName {
+        let account = self.apply_parent_accounts(account);
+        self.aliases.iter().rev().fold(account, |a, alias| alias.apply(&a))
+    }
+}
+
+/// Adds an amount to an account's running balance. Balances don't keep
+/// transaction prices or lots, only quantities per commodity.
+fn add_to_balance(balances: &mut HashMap<AccountName, MixedAmount>, account: &AccountName, amount: &MixedAmount) {
+    let amount = MixedAmount(amount.0.iter().map(|a| a.with_commodity(a.commodity.clone())).collect());
+    let balance = balances.get(account).cloned().unwrap_or(MixedAmount(vec!())) + amount;
+    balances.insert(account.clone(), balance);
+}
+
+/// The running balance of an account, including its subaccounts if `inclusive` is set
+fn account_balance(balances: &HashMap<AccountName, MixedAmount>, account: &AccountName, inclusive: bool) -> MixedAmount {
+    balances.iter()
+        .filter(|&(k, _)| k == account || (inclusive && k.is_subaccount_of(account)))
+        .fold(MixedAmount(vec!()), |acc, (_, v)| acc + v.clone())
+}
+
+/// The amount a balance assignment posts to move `balance` to the asserted value
+fn assigned_amount(assertion: &BalanceAssertion, balance: &MixedAmount) -> MixedAmount {
+    let current = if assertion.total {
+        balance.clone()
+    } else {
+        balance.filter_by_commodity(assertion.amount.commodity.clone())
+    };
+    let r = MixedAmount(vec!(assertion.amount.clone())) - current;
+    if r.0.is_empty() {
+        let mut zero = assertion.amount.clone();
+        zero.quantity = Quantity(Decimal::new(0, assertion.amount.quantity.0.scale));
+        MixedAmount(vec!(zero))
+    } else {
+        r
+    }
+}
+
+fn price_amount(price: &Price) -> Option<&Amount> {
+    match *price {
+        Price::UnitPrice(ref a) | Price::TotalPrice(ref a) => Some(a),
+        Price::None => None
+    }
+}
+
+fn merge_style(styles: &mut HashMap<Commodity, AmountStyle>, amount: &Amount) {
+    let style = styles.entry(amount.commodity.clone()).or_insert(amount.style.clone());
+    if amount.style.precision > style.precision {
+        style.precision = amount.style.precision;
+    }
+    if style.decimal_point.is_none() {
+        style.decimal_point = amount.style.decimal_point;
+    }
+    if style.digit_groups.is_none() {
+        style.digit_groups = amount.style.digit_groups.clone();
+    }
+}
+
+/// Gives an amount the style of its commodity, optionally keeping its precision
+fn restyle(amount: &mut Amount, styles: &HashMap<Commodity, AmountStyle>, keep_precision: bool) {
+    if let Some(s) = styles.get(&amount.commodity) {
+        let precision = amount.style.precision;
+        amount.style = s.clone();
+        if keep_precision {
+            amount.style.precision = precision;
+        }
+    }
+}
+
+fn restyle_price(price: &Price, styles: &HashMap<Commodity, AmountStyle>) -> Price {
+    match *price {
+        Price::UnitPrice(ref a) => { let mut a = a.clone(); restyle(&mut a, styles, true); Price::UnitPrice(a) },
+        Price::TotalPrice(ref a) => { let mut a = a.clone(); restyle(&mut a, styles, true); Price::TotalPrice(a) },
+        Price::None => Price::None
+    }
+}
+
+fn restyle_postings(postings: &mut Vec<Posting>, styles: &HashMap<Commodity, AmountStyle>) {
+    for p in postings.iter_mut() {
+        for a in p.amount.0.iter_mut() {
+            restyle(a, styles, false);
+            a.price = Rc::new(restyle_price(&a.price, styles));
+            if let Some(ref lot) = a.lot.clone() {
+                let mut l = (**lot).clone();
+                l.price = restyle_price(&l.price, styles);
+                a.lot = Some(Rc::new(l));
+            }
+        }
+        if let Some(ref mut b) = p.balance_assertion {
+            restyle(&mut b.amount, styles, false);
         }
     }
 }
@@ -37,6 +224,14 @@ pub struct Journal {
     pub market_prices: Vec<MarketPrice>,
     pub final_comment_lines: String,
     pub context: JournalContext,
+    pub declared_accounts: Vec<AccountDeclaration>,
+    pub declared_commodities: Vec<Commodity>,
+    pub declared_payees: Vec<String>,
+    pub declared_tags: Vec<String>,
+    /// Display styles declared for commodities, taking priority over inferred ones
+    pub declared_commodity_styles: HashMap<Commodity, AmountStyle>,
+    /// The display style of every commodity, once applied
+    pub commodity_styles: HashMap<Commodity, AmountStyle>,
 }
 
 impl Journal {
@@ -48,7 +243,13 @@ impl Journal {
             transactions: Vec::new(),
             market_prices: Vec::new(),
             final_comment_lines: String::from(""),
-            context: JournalContext::new()
+            context: JournalContext::new(),
+            declared_accounts: Vec::new(),
+            declared_commodities: Vec::new(),
+            declared_payees: Vec::new(),
+            declared_tags: Vec::new(),
+            declared_commodity_styles: HashMap::new(),
+            commodity_styles: HashMap::new()
         }
     }
 
@@ -82,6 +283,17 @@ impl Journal {
         self.add_item(|s, x| s.add_market_price_mut(x), market_price)
     }
 
+    /// Builds a price database from the journal's market prices, using the
+    /// commodity styles applied to the journal or else inferred from it
+    pub fn price_db(&self) -> PriceDb {
+        let mut db = PriceDb::from_market_prices(&self.market_prices);
+        let styles = if self.commodity_styles.is_empty() { self.infer_commodity_styles() } else { self.commodity_styles.clone() };
+        for (c, s) in styles {
+            db.set_style(c, s);
+        }
+        db
+    }
+
     /// Applies the given function to the journal to adds new items
     pub fn add_item<T, F>(&self, f: F, item: T) -> Journal where F : FnOnce(&mut Journal, T) {
         let mut x = self.clone();
@@ -99,8 +311,262 @@ impl Journal {
         self.transaction_at(transaction.index)
     }
 
+    /// Balances every transaction, inferring missing posting amounts.
+    ///
+    /// Transactions are processed in date order. A posting with a balance
+    /// assertion but no amount is a balance assignment: it receives whatever
+    /// amount brings its account's running balance to the asserted value,
+    /// computed before the rest of its transaction is balanced.
+    pub fn balance_transactions(&mut self) -> Result<(), BalanceError> {
+        let mut order: Vec<usize> = (0..self.transactions.len()).collect();
+        order.sort_by(|&a, &b| self.transactions[a].date.cmp(&self.transactions[b].date));
+
+        let mut balances: HashMap<AccountName, MixedAmount> = HashMap::new();
+        for i in order {
+            let mut t = self.transactions[i].clone();
+            let mut deltas: HashMap<AccountName, MixedAmount> = HashMap::new();
+            for p in t.postings.iter_mut() {
+                if p.amount.0.is_empty() {
+                    if let Some(ref a) = p.balance_assertion {
+                        let balance = account_balance(&balances, &p.account, a.inclusive) + account_balance(&deltas, &p.account, a.inclusive);
+                        p.amount = assigned_amount(a, &balance);
+                        p.amount_inferred = true;
+                    }
+                }
+                add_to_balance(&mut deltas, &p.account, &p.amount);
+            }
+
+            let t = try!(t.balance());
+            for p in t.postings.iter() {
+                add_to_balance(&mut balances, &p.account, &p.amount);
+            }
+            self.transactions[i] = t;
+        }
+        Ok(())
+    }
+
+    /// The transactions sorted by date, keeping the order in which they were
+    /// parsed for transactions on the same day
+    pub fn transactions_in_date_order(&self) -> Vec<&Transaction> {
+        let mut ts: Vec<&Transaction> = self.transactions.iter().collect();
+        ts.sort_by(|a, b| a.date.cmp(&b.date));
+        ts
+    }
+
+    /// The transactions generated by every periodic transaction within
+    /// `span`, in date order. Relative dates in period expressions are
+    /// resolved against `day`.
+    pub fn generate_periodic_transactions(&self, span: &DateSpan, day: Date<Local>) -> Result<Vec<Transaction>, String> {
+        let mut r = Vec::new();
+        for t in self.periodic_transactions.iter() {
+            r.extend(try!(t.generate(span, day)));
+        }
+        r.sort_by(|a, b| a.date.cmp(&b.date));
+        Ok(r)
+    }
+
+    /// Checks every balance assertion against the running balance of its
+    /// account, walking the transactions in date order. Expects the
+    /// transactions to have been balanced already.
+    pub fn check_balance_assertions(&self) -> Result<(), BalanceAssertionError> {
+        let mut balances: HashMap<AccountName, MixedAmount> = HashMap::new();
+        for t in self.transactions_in_date_order() {
+            for p in t.postings.iter() {
+                add_to_balance(&mut balances, &p.account, &p.amount);
+
+                let assertion = match p.balance_assertion {
+                    Some(ref a) => a,
+                    None => continue
+                };
+                let actual = account_balance(&balances, &p.account, assertion.inclusive);
+                let expected = MixedAmount(vec!(assertion.amount.clone()));
+                let actual = if assertion.total {
+                    actual
+                } else {
+                    actual.filter_by_commodity(assertion.amount.commodity.clone())
+                };
+                if !(actual.clone() - expected.clone()).is_zero() {
+                    let expected = if assertion.amount.is_zero() {
+                        format!("{}", assertion.amount.with_full_precision())
+                    } else {
+                        format!("{}", expected.with_full_precision())
+                    };
+                    return Err(BalanceAssertionError::new(t.source_pos.clone(), p.account.clone(), expected, format!("{}", actual.with_full_precision())));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Infers one display style per commodity from the amounts written in the
+    /// journal, in the order they were parsed, skipping amounts inferred
+    /// while balancing. The side, spacing, decimal mark and digit groups come
+    /// from the first amount that shows them and the precision is the largest
+    /// seen. Prices only count for commodities
+    /// that are never posted. Declared styles replace inferred ones.
+    pub fn infer_commodity_styles(&self) -> HashMap<Commodity, AmountStyle> {
+        let mut amounts: Vec<&Amount> = Vec::new();
+        let mut prices: Vec<&Amount> = Vec::new();
+        let mut posted: HashSet<&Commodity> = HashSet::new();
+        for p in self.transactions.iter().flat_map(|t| t.postings.iter()) {
+            posted.extend(p.amount.0.iter().map(|a| &a.commodity));
+            for a in p.amount.0.iter().filter(|_| !p.amount_inferred) {
+                amounts.push(a);
+                if let Some(p) = price_amount(&a.price) {
+                    prices.push(p);
+                }
+                if let Some(p) = a.lot.as_ref().and_then(|l| price_amount(&l.price)) {
+                    prices.push(p);
+                }
+            }
+            if let Some(ref a) = p.balance_assertion {
+                amounts.push(&a.amount);
+            }
+        }
+        amounts.extend(self.market_prices.iter().map(|p| &p.amount));
+
+        let mut styles: HashMap<Commodity, AmountStyle> = HashMap::new();
+        for a in amounts {
+            merge_style(&mut styles, a);
+        }
+        for a in prices.into_iter().filter(|a| !posted.contains(&a.commodity)) {
+            merge_style(&mut styles, a);
+        }
+        for (c, s) in self.declared_commodity_styles.iter() {
+            styles.insert(c.clone(), s.clone());
+        }
+        styles
+    }
+
+    /// Infers the commodity styles and gives every amount in the journal the
+    /// style of its commodity, so that amounts render consistently. Prices
+    /// keep their own precision. Should run after balancing, as balancing
+    /// tolerates residuals below the precision amounts were written with.
+    pub fn apply_commodity_styles(&mut self) {
+        self.commodity_styles = self.infer_commodity_styles();
+        let styles = self.commodity_styles.clone();
+        for t in self.transactions.iter_mut() {
+            restyle_postings(&mut t.postings, &styles);
+            *t = t.clone().tie_knot();
+        }
+        for t in self.modifier_transactions.iter_mut() {
+            restyle_postings(&mut t.postings, &styles);
+        }
+        for t in self.periodic_transactions.iter_mut() {
+            restyle_postings(&mut t.postings, &styles);
+        }
+        for p in self.market_prices.iter_mut() {
+            restyle(&mut p.amount, &styles, false);
+        }
+    }
+
+    /// Rewrites the accounts of all postings and account declarations with the
+    /// given aliases, applied in order. Aliases given when loading a journal
+    /// should be applied before it is queried.
+    pub fn apply_account_aliases(&mut self, aliases: &[AccountAlias]) {
+        let rename = |account: &AccountName| aliases.iter().fold(account.clone(), |a, alias| alias.apply(&a));
+        for t in self.transactions.iter_mut() {
+            for p in t.postings.iter_mut() {
+                p.account = rename(&p.account);
+            }
+            *t = t.clone().tie_knot();
+        }
+        for p in self.modifier_transactions.iter_mut().flat_map(|t| t.postings.iter_mut())
+            .chain(self.periodic_transactions.iter_mut().flat_map(|t| t.postings.iter_mut())) {
+            p.account = rename(&p.account);
+        }
+        for d in self.declared_accounts.iter_mut() {
+            d.account = rename(&d.account);
+        }
+    }
+
+    /// The type declared for an account or its nearest declared ancestor, or
+    /// else the one its top-level name suggests, as with `expenses:food`
+    pub fn account_type(&self, account: &AccountName) -> Option<AccountType> {
+        let declared = self.declared_accounts.iter()
+            .filter(|d| d.account_type.is_some() && (d.account == *account || account.is_subaccount_of(&d.account)))
+            .max_by_key(|d| d.account.depth());
+        match declared {
+            Some(d) => d.account_type,
+            None => account.components().first().and_then(|c| AccountType::from_str(c))
+        }
+    }
+
     /// Collects all postings from all transactions and flattens it into a list
     pub fn postings(&self) -> Vec<Posting> {
         self.transactions.iter().flat_map(|x| x.postings.iter()).map(|x| x.clone()).collect()
     }
 }
+
+#[test]
+fn balance_assertion_test() {
+    use parser::parse_journal;
+    let check = |s: &str| {
+        let mut j = parse_journal(s).unwrap();
+        j.balance_transactions().unwrap();
+        j.check_balance_assertions()
+    };
+
+    let journal = "2016/01/02 b\n    assets:bank  $10 = $15\n    assets:bank:sub  5 EUR =* 5 EUR\n    equity\n\n\
+                   2016/01/01 a\n    assets:bank  $5\n    equity\n";
+    assert!(check(journal).is_ok());
+    assert!(check("2016/01/01 a\n    a  $5\n    a  1 EUR = $5\n    b\n").is_ok());
+    assert!(check("2016/01/01 a\n    a  $5\n    a  1 EUR == $5\n    b\n").is_err());
+    assert!(check("2016/01/01 a\n    a  $5\n    a  1 EUR\n    b\n2016/01/02 x\n    a:b  $1\n    b\n    a  $0 =* $6\n").is_ok());
+    assert!(check("2016/01/01 a\n    a  $5\n    a  1 EUR\n    b\n2016/01/02 x\n    a:b  $1\n    b\n    a  $0 ==* $6\n").is_err());
+
+    let err = check("2016/01/01 a\n    a  $5\n    b\n\n2016/01/02 b\n    a  $1 = $7\n    b\n").err().unwrap();
+    assert!(err.source_pos.line() == 5);
+    assert!(err.expected == "$7" && err.actual == "$6");
+
+    let mut j = parse_journal("2016/01/01 a\n    a  $7.00 = $7.00\n    b\n").unwrap();
+    j.transactions[0].postings[0].amount.0[0].quantity = Quantity(Decimal::new(7001, 3));
+    let err = j.check_balance_assertions().err().unwrap();
+    assert!(err.expected == "$7.00" && err.actual == "$7.001");
+}
+
+#[test]
+fn balance_assignment_test() {
+    use parser::parse_journal;
+    use commodity::Commodity;
+    let mut j = parse_journal("2016/01/31 reconcile\n    assets:bank  = $65\n    expenses:fees\n\n\
+                               2016/01/01 opening\n    assets:bank  = $100\n    equity:opening\n\n\
+                               2016/01/05 spend\n    expenses  $30\n    assets:bank\n\n\
+                               2016/02/01 close\n    assets:bank  == $0\n    equity\n").unwrap();
+    j.balance_transactions().unwrap();
+    let usd = Commodity::new(String::from("$"));
+    let quantity = |t: usize, p: usize| j.transactions[t].postings[p].amount.get(&usd).map(|x| x.quantity);
+    assert!(quantity(0, 0) == Some(Quantity(Decimal::new(-5, 0))));
+    assert!(quantity(0, 1) == Some(Quantity(Decimal::new(5, 0))));
+    assert!(quantity(1, 0) == Some(Quantity(Decimal::new(100, 0))));
+    assert!(quantity(3, 0) == Some(Quantity(Decimal::new(-65, 0))));
+    assert!(quantity(3, 1) == Some(Quantity(Decimal::new(65, 0))));
+    assert!(j.check_balance_assertions().is_ok());
+
+    let mut priced = parse_journal("2016/01/01 buy\n    a  10 AAPL @ $5\n    b\n\n\
+                                    2016/01/02 check\n    a  = 10 AAPL\n    b\n").unwrap();
+    priced.balance_transactions().unwrap();
+    let check = &priced.transactions[1];
+    assert!(check.postings[0].amount.0.len() == 1 && check.postings[0].amount.is_zero());
+    assert!(*check.postings[0].amount.0[0].price == Price::None);
+    assert!(check.postings[1].amount.is_zero() && check.postings[1].amount.get(&usd).is_none());
+}
+
+#[test]
+fn commodity_style_test() {
+    use parser::{ parse_amount, parse_journal };
+    let mut journal = parse_journal("2016/01/01 a\n    a  $1,000\n    b  $-999.50\n    c\n\n\
+                                     2016/01/02 b\n    a  3 EUR @ $1.1234\n    b\n\n\
+                                     2016/01/03 c\n    a  10 AAPL @ 1.234,5 USD\n    b\n").unwrap();
+    journal.balance_transactions().unwrap();
+    journal.declared_commodity_styles.insert(Commodity::new(String::from("EUR")), parse_amount("1.000,00 EUR").unwrap().style);
+    journal.apply_commodity_styles();
+
+    let show = |t: usize, p: usize| format!("{}", journal.transactions[t].postings[p].amount);
+    assert_eq!(show(0, 0), "$1,000.00");
+    assert_eq!(show(0, 1), "-$999.50");
+    assert_eq!(show(1, 0), "3,00 EUR @ $1.1234");
+    assert_eq!(show(1, 1), "-$3.37");
+    assert_eq!(show(2, 0), "10 AAPL @ 1.234,5 USD");
+    assert!(journal.transactions[0].postings[0].transaction.as_ref().unwrap().postings[0].amount.0[0].style.precision == 2);
+}
@@ -1,18 +1,25 @@
 #![feature(zero_one)]
 #![feature(iter_arith)]
 #![feature(slice_patterns)]
+#![feature(i128_type)]
 
 extern crate chrono;
 extern crate regex;
+pub mod account_name;
 pub mod amount;
 pub mod decimal;
 pub mod commodity;
+pub mod dates;
+pub mod gains;
 pub mod journal;
+pub mod lot;
+pub mod parser;
 pub mod posting;
 pub mod price;
 pub mod quantity;
 pub mod query;
 pub mod transaction;
+pub mod valuation;
 
 #[cfg(test)]
 mod test {
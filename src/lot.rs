@@ -0,0 +1,519 @@
+use std::fmt;
+use std::error;
+use std::collections::BTreeMap;
+use chrono::{ Date, Local };
+
+use account_name::AccountName;
+use amount::Amount;
+use commodity::Commodity;
+use decimal::Decimal;
+use journal::{ AccountType, Journal };
+use price::Price;
+use quantity::Quantity;
+use transaction::{ GenericSourcePos, Transaction };
+
+/// Number of decimal places kept when a total cost is spread over its units
+pub const UNIT_COST_SCALE: u32 = 10;
+
+/// A lot annotation, as in `10 AAPL {150 USD} [2016/01/04] (note)`
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Lot {
+    pub price: Price,
+    pub date: Option<Date<Local>>,
+    pub note: Option<String>
+}
+
+impl Lot {
+    pub fn new() -> Lot {
+        Lot {
+            price: Price::None,
+            date: None,
+            note: None
+        }
+    }
+
+    /// The cost of a single unit of a lot holding `quantity` units
+    pub fn unit_cost(&self, quantity: &Quantity) -> Option<Amount> {
+        unit_price(&self.price, quantity)
+    }
+}
+
+fn unit_price(price: &Price, quantity: &Quantity) -> Option<Amount> {
+    match *price {
+        Price::None => None,
+        Price::UnitPrice(ref p) => Some(p.with_commodity(p.commodity.clone())),
+        Price::TotalPrice(ref p) => {
+            let mut r = p.with_commodity(p.commodity.clone());
+            r.quantity = Quantity(p.quantity.0.abs().div_with_scale(&quantity.0.abs(), UNIT_COST_SCALE).normalize());
+            Some(r)
+        }
+    }
+}
+
+/// Which lots a sale takes its units from when it doesn't name a lot
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LotSelection {
+    Fifo,
+    Lifo,
+    Average
+}
+
+/// Units of a commodity held in an account that were acquired together, and
+/// what they cost in total
+#[derive(Clone, PartialEq, Eq)]
+pub struct Holding {
+    pub commodity: Commodity,
+    pub quantity: Quantity,
+    pub cost: Amount,
+    pub date: Date<Local>,
+    pub note: Option<String>
+}
+
+impl Holding {
+    pub fn cost_basis(&self) -> Amount {
+        self.cost.clone()
+    }
+
+    /// The cost of a single unit, rounded to `UNIT_COST_SCALE` places
+    pub fn unit_cost(&self) -> Amount {
+        let mut r = self.cost.clone();
+        r.quantity = Quantity(self.cost.quantity.0.div_with_scale(&self.quantity.0, UNIT_COST_SCALE).normalize());
+        r
+    }
+
+    /// Splits `quantity` units off the holding along with their share of its
+    /// cost. Whatever cost is left stays with the rest of the holding, so
+    /// the parts always add up to the original cost.
+    fn take(&mut self, quantity: Quantity) -> Holding {
+        let mut part = self.clone();
+        if quantity < self.quantity {
+            part.cost.quantity = Quantity((self.cost.quantity.0 * quantity.0).div_with_scale(&self.quantity.0, UNIT_COST_SCALE).normalize());
+        }
+        part.quantity = quantity.clone();
+        self.quantity = self.quantity.clone() - quantity;
+        self.cost.quantity = self.cost.quantity.clone() - part.cost.quantity.clone();
+        part
+    }
+
+    /// Whether the holding is the one named by a lot annotation on a sale of
+    /// `quantity` units
+    fn matches(&self, lot: &Lot, quantity: &Quantity) -> bool {
+        let price_matches = match lot.unit_cost(quantity) {
+            Some(c) => c.commodity == self.cost.commodity && (c.quantity - self.unit_cost().quantity).is_zero(),
+            None => true
+        };
+        price_matches && lot.date.map_or(true, |d| d == self.date) && lot.note.as_ref().map_or(true, |n| Some(n) == self.note.as_ref())
+    }
+}
+
+/// A sale of units taken from a single lot
+#[derive(Clone, PartialEq, Eq)]
+pub struct Disposal {
+    pub account: AccountName,
+    pub commodity: Commodity,
+    pub quantity: Quantity,
+    pub acquired: Date<Local>,
+    pub disposed: Date<Local>,
+    pub cost_basis: Amount,
+    pub proceeds: Option<Amount>,
+    pub source_pos: GenericSourcePos
+}
+
+impl Disposal {
+    /// Proceeds less cost basis, if the sale had a price in the cost basis' commodity
+    pub fn gain(&self) -> Option<Amount> {
+        match self.proceeds {
+            Some(ref p) if p.commodity == self.cost_basis.commodity => {
+                let mut r = p.clone();
+                r.quantity = p.quantity.clone() - self.cost_basis.quantity.clone();
+                Some(r)
+            },
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LotErrorKind {
+    InsufficientQuantity(String, String, String),
+    NoMatchingLot(String),
+    MixedCostCommodities(String),
+}
+
+impl LotErrorKind {
+    fn desc(&self) -> String {
+        match *self {
+            LotErrorKind::InsufficientQuantity(ref c, ref wanted, ref held) =>
+                format!("cannot sell {} {}, only {} held", wanted, c, held),
+            LotErrorKind::NoMatchingLot(ref c) => format!("no lot of {} matches the sale", c),
+            LotErrorKind::MixedCostCommodities(ref c) =>
+                format!("cannot average the cost of {}, its lots were bought in different commodities", c),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LotError {
+    pub kind: LotErrorKind,
+    pub account: AccountName,
+    pub source_pos: GenericSourcePos,
+    desc: String,
+}
+
+impl LotError {
+    fn new(kind: LotErrorKind, account: AccountName, source_pos: GenericSourcePos) -> Self {
+        let desc = format!("{} in account {}", kind.desc(), account);
+        LotError { kind: kind, account: account, source_pos: source_pos, desc: desc }
+    }
+}
+
+impl fmt::Display for LotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.source_pos, self.desc)
+    }
+}
+
+impl error::Error for LotError {
+    fn description(&self) -> &str {
+        &self.desc
+    }
+}
+
+/// The open lots held in an account, in the order they were acquired
+#[derive(Clone, PartialEq, Eq)]
+pub struct Inventory {
+    pub holdings: Vec<Holding>
+}
+
+impl Inventory {
+    pub fn new() -> Inventory {
+        Inventory { holdings: Vec::new() }
+    }
+
+    /// The number of units of `commodity` held
+    pub fn quantity(&self, commodity: &Commodity) -> Quantity {
+        self.holdings.iter()
+            .filter(|h| h.commodity == *commodity)
+            .fold(Quantity(Decimal::new(0, 0)), |acc, h| acc + h.quantity.clone())
+    }
+
+    pub fn acquire(&mut self, holding: Holding) {
+        self.holdings.push(holding);
+    }
+
+    /// Removes `quantity` units of `commodity` and returns the parts of the
+    /// lots they were taken from. A lot annotation restricts the sale to
+    /// matching lots; otherwise `selection` decides the order.
+    pub fn dispose(&mut self, commodity: &Commodity, quantity: &Quantity, lot: Option<&Lot>, selection: LotSelection) -> Result<Vec<Holding>, LotErrorKind> {
+        let mut candidates: Vec<usize> = (0..self.holdings.len())
+            .filter(|&i| self.holdings[i].commodity == *commodity && lot.map_or(true, |l| self.holdings[i].matches(l, quantity)))
+            .collect();
+        if candidates.is_empty() && lot.is_some() {
+            return Err(LotErrorKind::NoMatchingLot(commodity.symbol().to_string()));
+        }
+        let held = candidates.iter().fold(Quantity(Decimal::new(0, 0)), |acc, &i| acc + self.holdings[i].quantity.clone());
+        if held < *quantity {
+            return Err(LotErrorKind::InsufficientQuantity(commodity.symbol().to_string(), format!("{}", quantity.0), format!("{}", held.0)));
+        }
+
+        candidates.sort_by(|&a, &b| self.holdings[a].date.cmp(&self.holdings[b].date));
+        match selection {
+            LotSelection::Lifo if lot.is_none() => candidates.reverse(),
+            LotSelection::Average if lot.is_none() => {
+                let merged = try!(self.average(&candidates, held));
+                self.holdings = self.holdings.iter().enumerate()
+                    .filter(|&(i, _)| !candidates.contains(&i))
+                    .map(|(_, h)| h.clone())
+                    .collect();
+                self.holdings.push(merged);
+                candidates = vec!(self.holdings.len() - 1);
+            },
+            _ => ()
+        }
+
+        let mut remaining = quantity.clone();
+        let mut taken = Vec::new();
+        for i in candidates {
+            if remaining.is_zero() {
+                break;
+            }
+            let h = &mut self.holdings[i];
+            let q = if h.quantity < remaining { h.quantity.clone() } else { remaining.clone() };
+            remaining = remaining - q.clone();
+            taken.push(h.take(q));
+        }
+        self.holdings.retain(|h| !h.quantity.is_zero());
+        Ok(taken)
+    }
+
+    /// Merges the given holdings into one at their combined cost, dated at
+    /// the earliest acquisition
+    fn average(&self, candidates: &Vec<usize>, held: Quantity) -> Result<Holding, LotErrorKind> {
+        let first = self.holdings[candidates[0]].clone();
+        let mut cost = first.cost.clone();
+        cost.quantity = Quantity(Decimal::new(0, 0));
+        for &i in candidates {
+            let h = &self.holdings[i];
+            if h.cost.commodity != first.cost.commodity {
+                return Err(LotErrorKind::MixedCostCommodities(h.commodity.symbol().to_string()));
+            }
+            cost.quantity = cost.quantity.clone() + h.cost.quantity.clone();
+        }
+        Ok(Holding {
+            commodity: first.commodity.clone(),
+            quantity: held,
+            cost: cost,
+            date: first.date,
+            note: None
+        })
+    }
+}
+
+/// The open lots of every account and the sales made from them
+#[derive(Clone, PartialEq, Eq)]
+pub struct LotBook {
+    pub inventories: BTreeMap<AccountName, Inventory>,
+    pub disposals: Vec<Disposal>
+}
+
+impl LotBook {
+    /// Walks the journal's transactions in date order. Amounts received with
+    /// a lot price or transaction price open a lot, dated at the lot date if
+    /// given and the transaction date otherwise. Amounts given away close
+    /// lots of that commodity, and their transaction price is taken as the
+    /// sale's proceeds. Amounts given away without a price to an account
+    /// that receives them without a cost in the same transaction move their
+    /// lots there instead, unless it is an expense account, where they are
+    /// used up. Expects the transactions to have been balanced.
+    pub fn from_journal(journal: &Journal, selection: LotSelection) -> Result<LotBook, LotError> {
+        LotBook::from_transactions(journal.transactions_in_date_order(), selection,
+                                   &|a| journal.account_type(a) == Some(AccountType::Expense))
+    }
+
+    /// Like `from_journal`, for transactions already in date order, with
+    /// `is_expense` telling the expense accounts
+    pub fn from_transactions(transactions: Vec<&Transaction>, selection: LotSelection, is_expense: &Fn(&AccountName) -> bool) -> Result<LotBook, LotError> {
+        let mut book = LotBook { inventories: BTreeMap::new(), disposals: Vec::new() };
+        for t in transactions {
+            let mut receipts: Vec<(AccountName, Amount)> = t.postings.iter()
+                .filter(|p| !is_expense(&p.account))
+                .flat_map(|p| p.amount.0.iter()
+                    .filter(|a| !a.is_negative() && acquisition_cost(a).is_none())
+                    .map(move |a| (p.account.clone(), a.clone())))
+                .collect();
+            for p in t.postings.iter() {
+                for a in p.amount.0.iter() {
+                    let lot = a.lot.as_ref().map(|x| x.as_ref());
+                    if !a.is_negative() {
+                        if let Some(c) = acquisition_cost(a) {
+                            book.inventories.entry(p.account.clone()).or_insert(Inventory::new()).acquire(Holding {
+                                commodity: a.commodity.clone(),
+                                quantity: a.quantity.clone(),
+                                cost: c,
+                                date: lot.and_then(|l| l.date).unwrap_or(t.date),
+                                note: lot.and_then(|l| l.note.clone())
+                            });
+                        }
+                    } else {
+                        let sold = a.quantity.clone().abs();
+                        let taken = {
+                            let inventory = book.inventories.entry(p.account.clone()).or_insert(Inventory::new());
+                            if lot.is_none() && inventory.quantity(&a.commodity).is_zero() {
+                                continue;
+                            }
+                            try!(inventory.dispose(&a.commodity, &sold, lot, selection)
+                                .map_err(|k| LotError::new(k, p.account.clone(), t.source_pos.clone())))
+                        };
+                        let (taken, proceeds) = match *a.price {
+                            Price::None => {
+                                let (moved, rest) = transfer(taken, &mut receipts);
+                                for (account, h) in moved {
+                                    book.inventories.entry(account).or_insert(Inventory::new()).acquire(h);
+                                }
+                                (rest, None)
+                            },
+                            _ => (taken, Some((-a.clone()).cost()))
+                        };
+                        book.disposals.extend(split_proceeds(&taken, &sold, proceeds).into_iter().map(|(h, proceeds)| Disposal {
+                            account: p.account.clone(),
+                            commodity: h.commodity.clone(),
+                            quantity: h.quantity.clone(),
+                            acquired: h.date,
+                            disposed: t.date,
+                            cost_basis: h.cost_basis(),
+                            proceeds: proceeds,
+                            source_pos: t.source_pos.clone()
+                        }));
+                    }
+                }
+            }
+        }
+        book.inventories = book.inventories.into_iter().filter(|&(_, ref i)| !i.holdings.is_empty()).collect();
+        Ok(book)
+    }
+
+    /// The sum of all realised gains, by commodity
+    pub fn realised_gains(&self) -> Vec<Amount> {
+        let mut r: Vec<Amount> = Vec::new();
+        for g in self.disposals.iter().filter_map(|d| d.gain()) {
+            let pos = r.iter().position(|x| x.commodity == g.commodity);
+            match pos {
+                Some(i) => r[i].quantity = r[i].quantity.clone() + g.quantity,
+                None => r.push(g)
+            }
+        }
+        r
+    }
+}
+
+/// The total cost of an amount received with a lot price or transaction price
+fn acquisition_cost(a: &Amount) -> Option<Amount> {
+    a.lot.as_ref().and_then(|l| total_price(&l.price, &a.quantity)).or_else(|| total_price(&a.price, &a.quantity))
+}
+
+fn total_price(price: &Price, quantity: &Quantity) -> Option<Amount> {
+    match *price {
+        Price::None => None,
+        Price::UnitPrice(ref p) => {
+            let mut r = p.with_commodity(p.commodity.clone());
+            r.quantity = p.quantity.clone() * quantity.clone().abs();
+            Some(r)
+        },
+        Price::TotalPrice(ref p) => {
+            let mut r = p.with_commodity(p.commodity.clone());
+            r.quantity = p.quantity.clone().abs();
+            Some(r)
+        }
+    }
+}
+
+/// Hands lots given away in a transaction to the accounts that receive the
+/// commodity without a cost in it, keeping their cost and acquisition date.
+/// Returns the moved lots with their new accounts, and the lots left over.
+fn transfer(taken: Vec<Holding>, receipts: &mut Vec<(AccountName, Amount)>) -> (Vec<(AccountName, Holding)>, Vec<Holding>) {
+    let mut moved = Vec::new();
+    let mut rest = Vec::new();
+    for mut h in taken {
+        for r in receipts.iter_mut() {
+            if h.quantity.is_zero() {
+                break;
+            }
+            if r.1.commodity != h.commodity || r.1.quantity.is_zero() {
+                continue;
+            }
+            let q = if h.quantity < r.1.quantity { h.quantity.clone() } else { r.1.quantity.clone() };
+            r.1.quantity = r.1.quantity.clone() - q.clone();
+            moved.push((r.0.clone(), h.take(q)));
+        }
+        if !h.quantity.is_zero() {
+            rest.push(h);
+        }
+    }
+    (moved, rest)
+}
+
+/// Shares the proceeds of a sale out over the lots it was taken from in
+/// proportion to their quantities; the last lot receives the remainder so
+/// the shares add up exactly
+fn split_proceeds(taken: &Vec<Holding>, sold: &Quantity, proceeds: Option<Amount>) -> Vec<(Holding, Option<Amount>)> {
+    let total = match proceeds {
+        Some(p) => p,
+        None => return taken.iter().map(|h| (h.clone(), None)).collect()
+    };
+    let mut left = total.quantity.clone();
+    let mut r = Vec::new();
+    for (i, h) in taken.iter().enumerate() {
+        let mut share = total.clone();
+        if i + 1 < taken.len() {
+            share.quantity = Quantity((total.quantity.0 * h.quantity.0).div_with_scale(&sold.0, UNIT_COST_SCALE).normalize());
+            left = left - share.quantity.clone();
+        } else {
+            share.quantity = left.clone();
+        }
+        r.push((h.clone(), Some(share)));
+    }
+    r
+}
+
+#[cfg(test)]
+fn book(selection: LotSelection, sale: &str) -> Result<LotBook, LotError> {
+    use parser::parse_journal;
+    let mut journal = parse_journal(&format!("2016/01/04 buy\n    assets:broker  10 AAPL {{100 USD}}\n    assets:cash\n\n\
+                                              2016/02/01 buy\n    assets:broker  10 AAPL @ 120 USD\n    assets:cash\n\n\
+                                              2016/06/01 sell\n    assets:broker  {}\n    assets:cash\n", sale)).unwrap();
+    journal.balance_transactions().unwrap();
+    LotBook::from_journal(&journal, selection)
+}
+
+#[cfg(test)]
+fn summary(book: &LotBook) -> Vec<(i128, i128, i128)> {
+    book.disposals.iter().map(|d| (d.quantity.0.unscaled, d.cost_basis.quantity.0.unscaled, d.gain().unwrap().quantity.0.unscaled)).collect()
+}
+
+#[test]
+fn lot_selection_test() {
+    use chrono::TimeZone;
+    let fifo = book(LotSelection::Fifo, "-15 AAPL @ 130 USD").unwrap();
+    assert_eq!(summary(&fifo), vec!((10, 1000, 300), (5, 600, 50)));
+    assert!(fifo.disposals[0].acquired == Local.ymd(2016, 1, 4));
+    let remaining = &fifo.inventories[&AccountName::new(String::from("assets:broker"))];
+    assert!(remaining.quantity(&Commodity::new(String::from("AAPL"))) == Quantity(Decimal::new(5, 0)));
+
+    assert_eq!(summary(&book(LotSelection::Lifo, "-15 AAPL @ 130 USD").unwrap()), vec!((10, 1200, 100), (5, 500, 150)));
+    assert_eq!(summary(&book(LotSelection::Average, "-15 AAPL @ 130 USD").unwrap()), vec!((15, 1650, 300)));
+    assert_eq!(summary(&book(LotSelection::Fifo, "-5 AAPL {120 USD} @ 130 USD").unwrap()), vec!((5, 600, 50)));
+    assert_eq!(summary(&book(LotSelection::Fifo, "-4 AAPL [2016/01/04] @@ 520 USD").unwrap()), vec!((4, 400, 120)));
+    assert_eq!(summary(&book(LotSelection::Fifo, "-5 AAPL {{600 USD}} @ 130 USD").unwrap()), vec!((5, 600, 50)));
+
+    let total: Vec<i128> = book(LotSelection::Fifo, "-20 AAPL @@ 2500 USD").unwrap().realised_gains().iter().map(|x| x.quantity.0.unscaled).collect();
+    assert_eq!(total, vec!(300));
+
+    let err = book(LotSelection::Fifo, "-25 AAPL @ 130 USD").err().unwrap();
+    assert!(err.kind == LotErrorKind::InsufficientQuantity(String::from("AAPL"), String::from("25"), String::from("20")));
+    assert!(book(LotSelection::Fifo, "-5 AAPL {99 USD}").is_err());
+}
+
+#[test]
+fn lot_transfer_test() {
+    use chrono::TimeZone;
+    use parser::parse_journal;
+    let mut journal = parse_journal("2016/01/04 buy\n    assets:broker  10 AAPL {100 USD}\n    assets:cash\n\n\
+                                     2016/03/01 move\n    assets:ira  6 AAPL\n    assets:broker  -6 AAPL\n\n\
+                                     2016/06/01 sell\n    assets:ira  -4 AAPL @ 130 USD\n    assets:cash\n").unwrap();
+    journal.balance_transactions().unwrap();
+    let book = LotBook::from_journal(&journal, LotSelection::Fifo).unwrap();
+    assert_eq!(summary(&book), vec!((4, 400, 120)));
+    assert!(book.disposals[0].acquired == Local.ymd(2016, 1, 4));
+    let aapl = Commodity::new(String::from("AAPL"));
+    assert!(book.inventories[&AccountName::new(String::from("assets:broker"))].quantity(&aapl) == Quantity(Decimal::new(4, 0)));
+    assert!(book.inventories[&AccountName::new(String::from("assets:ira"))].holdings[0].cost_basis().quantity == Quantity(Decimal::new(200, 0)));
+}
+
+#[test]
+fn lot_expense_test() {
+    use parser::parse_journal;
+    let check = |s: &str| {
+        let mut journal = parse_journal(&format!("2016/01/04 buy\n    assets:broker  10 AAPL {{100 USD}}\n    assets:cash\n\n{}", s)).unwrap();
+        journal.balance_transactions().unwrap();
+        let book = LotBook::from_journal(&journal, LotSelection::Fifo).unwrap();
+        assert_eq!(book.inventories.len(), 1);
+        assert!(book.disposals.len() == 1 && book.disposals[0].proceeds.is_none());
+        assert!(book.disposals[0].cost_basis.quantity == Quantity(Decimal::new(200, 0)));
+    };
+    check("2016/03/01 gift\n    expenses:gifts  2 AAPL\n    assets:broker  -2 AAPL\n");
+    check("account gifts  ; type: X\n2016/03/01 gift\n    gifts:family  2 AAPL\n    assets:broker  -2 AAPL\n");
+}
+
+#[test]
+fn lot_total_cost_test() {
+    let costs = |sales: &str| {
+        use parser::parse_journal;
+        let mut journal = parse_journal(&format!("2016/01/04 buy\n    assets:broker  3 AAPL {{{{100 USD}}}}\n    assets:cash\n\n{}", sales)).unwrap();
+        journal.balance_transactions().unwrap();
+        let book = LotBook::from_journal(&journal, LotSelection::Fifo).unwrap();
+        book.disposals.iter().map(|d| d.cost_basis.quantity.clone()).fold(Quantity(Decimal::new(0, 0)), |acc, q| acc + q)
+    };
+    assert!(costs("2016/06/01 sell\n    assets:broker  -3 AAPL @ 40 USD\n    assets:cash\n") == Quantity(Decimal::new(100, 0)));
+    assert!(costs("2016/06/01 sell\n    assets:broker  -1 AAPL @ 40 USD\n    assets:cash\n\n\
+                   2016/07/01 sell\n    assets:broker  -2 AAPL @ 40 USD\n    assets:cash\n") == Quantity(Decimal::new(100, 0)));
+}
@@ -0,0 +1,1101 @@
+use std::fmt;
+use std::error;
+use std::rc::Rc;
+use std::fs;
+use std::io::Read;
+use std::mem;
+use std::path::{ Path, PathBuf };
+use chrono::{ Date, Local, TimeZone };
+
+use account_name::AccountName;
+use amount::{ Amount, AmountStyle, DigitGroupStyle, MixedAmount, Side };
+use commodity::Commodity;
+use decimal::{ Decimal, DecimalFormat };
+use journal::{ AccountDeclaration, AccountType, Journal, JournalContext, MarketPrice, Year };
+use lot::Lot;
+use posting::{ BalanceAssertion, ClearedStatus, Posting, PostingType, Tag };
+use price::Price;
+use quantity::Quantity;
+use transaction::{ GenericSourcePos, ModifierTransaction, PeriodicTransaction, Transaction };
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    InvalidDate(String),
+    MissingYear(String),
+    InvalidAmount(String),
+    InvalidPosting(String),
+    InvalidDirective(String),
+    InvalidAlias(String),
+    IncludeFailed(String, String, String),
+    IncludeCycle(String),
+    UnexpectedInput(String),
+}
+
+impl ParseErrorKind {
+    fn desc(&self) -> String {
+        match *self {
+            ParseErrorKind::InvalidDate(ref s) => format!("invalid date '{}'", s),
+            ParseErrorKind::MissingYear(ref s) => format!("date '{}' has no year and no year directive precedes it", s),
+            ParseErrorKind::InvalidAmount(ref s) => format!("invalid amount '{}'", s),
+            ParseErrorKind::InvalidPosting(ref s) => format!("invalid posting '{}'", s),
+            ParseErrorKind::InvalidDirective(ref s) => format!("invalid directive '{}'", s),
+            ParseErrorKind::InvalidAlias(ref s) => s.clone(),
+            ParseErrorKind::IncludeFailed(ref file, ref reason, ref chain) if chain.is_empty() =>
+                format!("cannot read '{}': {}", file, reason),
+            ParseErrorKind::IncludeFailed(ref file, ref reason, ref chain) =>
+                format!("cannot include '{}': {} (included from {})", file, reason, chain),
+            ParseErrorKind::IncludeCycle(ref chain) => format!("include cycle: {}", chain),
+            ParseErrorKind::UnexpectedInput(ref s) => format!("unexpected input '{}'", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub source_pos: GenericSourcePos,
+    desc: String,
+}
+
+impl ParseError {
+    fn new(kind: ParseErrorKind, source_pos: GenericSourcePos) -> Self {
+        let desc = kind.desc();
+        ParseError { kind: kind, source_pos: source_pos, desc: desc }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.source_pos.file(), self.source_pos.line(), self.source_pos.column(), self.desc)
+    }
+}
+
+impl error::Error for ParseError {
+    fn description(&self) -> &str {
+        &self.desc
+    }
+}
+
+/// Parses a complete journal in hledger format
+pub fn parse_journal(input: &str) -> Result<Journal, ParseError> {
+    parse_journal_file(String::new(), input)
+}
+
+/// Parses a complete journal in hledger format, recording `file` as the
+/// origin of every transaction. Files named by `include` directives are
+/// resolved relative to the directory of `file`.
+pub fn parse_journal_file(file: String, input: &str) -> Result<Journal, ParseError> {
+    parse_journal_file_with_context(file, input, JournalContext::new())
+}
+
+/// Like `parse_journal_file`, starting from the given context instead of an
+/// empty one, e.g. to supply the year of dates written without one
+pub fn parse_journal_file_with_context(file: String, input: &str, context: JournalContext) -> Result<Journal, ParseError> {
+    let stack = fs::canonicalize(&file).into_iter().map(|p| (p, file.clone())).collect();
+    let mut journal = Journal::new();
+    journal.context = context;
+    let mut parser = JournalParser::new(file, input, journal, stack);
+    try!(parser.parse_lines());
+    parser.journal.final_comment_lines = parser.take_comment_lines();
+    Ok(parser.journal)
+}
+
+/// Reads and parses a journal file along with the files it includes. A file
+/// that can't be read is reported at line 0 of its path.
+pub fn read_journal_file<P: AsRef<Path>>(path: P) -> Result<Journal, ParseError> {
+    let path = path.as_ref();
+    let name = path.to_string_lossy().into_owned();
+    let input = try!(read_file(path).map_err(|e| {
+        ParseError::new(ParseErrorKind::IncludeFailed(name.clone(), e, String::new()), GenericSourcePos::new(name.clone(), 0, 0))
+    }));
+    let stack = fs::canonicalize(path).into_iter().map(|p| (p, name.clone())).collect();
+    let mut parser = JournalParser::new(name, &input, Journal::new(), stack);
+    try!(parser.parse_lines());
+    parser.journal.final_comment_lines = parser.take_comment_lines();
+    Ok(parser.journal)
+}
+
+fn read_file(path: &Path) -> Result<String, String> {
+    let mut input = String::new();
+    try!(fs::File::open(path).and_then(|mut f| f.read_to_string(&mut input)).map_err(|e| e.to_string()));
+    Ok(input)
+}
+
+struct JournalParser<'a> {
+    file: String,
+    lines: Vec<&'a str>,
+    line: usize,
+    journal: Journal,
+    comment_lines: Vec<String>,
+    /// The canonical paths of the files being parsed, outermost first, with
+    /// their paths as written
+    include_stack: Vec<(PathBuf, String)>,
+}
+
+impl<'a> JournalParser<'a> {
+    fn new(file: String, input: &'a str, journal: Journal, include_stack: Vec<(PathBuf, String)>) -> JournalParser<'a> {
+        JournalParser {
+            file: file,
+            lines: input.lines().collect(),
+            line: 0,
+            journal: journal,
+            comment_lines: Vec::new(),
+            include_stack: include_stack
+        }
+    }
+
+    fn parse_lines(&mut self) -> Result<(), ParseError> {
+        while self.line < self.lines.len() {
+            let l = self.lines[self.line];
+            match l.chars().next() {
+                None => self.line += 1,
+                Some(c) if c.is_digit(10) => try!(self.parse_transaction()),
+                Some('~') => try!(self.parse_periodic_transaction()),
+                Some('=') => try!(self.parse_modifier_transaction()),
+                Some(c) if c.is_alphabetic() => try!(self.parse_directive()),
+                Some(';') | Some('#') | Some('*') => {
+                    self.comment_lines.push(l.to_string());
+                    self.line += 1;
+                },
+                Some(' ') | Some('\t') if l.trim().is_empty() => self.line += 1,
+                Some(' ') | Some('\t') if l.trim_left().starts_with(';') => {
+                    self.comment_lines.push(l.to_string());
+                    self.line += 1;
+                },
+                _ => return Err(self.error(ParseErrorKind::UnexpectedInput(l.trim().to_string()), 1))
+            }
+        }
+        Ok(())
+    }
+
+    fn error(&self, kind: ParseErrorKind, column: usize) -> ParseError {
+        ParseError::new(kind, self.source_pos(column))
+    }
+
+    fn source_pos(&self, column: usize) -> GenericSourcePos {
+        GenericSourcePos::new(self.file.clone(), self.line + 1, column)
+    }
+
+    fn take_comment_lines(&mut self) -> String {
+        let r = self.comment_lines.join("\n");
+        self.comment_lines.clear();
+        r
+    }
+
+    fn parse_transaction(&mut self) -> Result<(), ParseError> {
+        let source_pos = self.source_pos(1);
+        let (header, comment) = split_comment(self.lines[self.line]);
+        let mut rest = header.trim_right();
+
+        let date_end = rest.find(|c: char| c.is_whitespace()).unwrap_or(rest.len());
+        let (date_str, date2_str) = match rest[..date_end].find('=') {
+            Some(i) => (&rest[..i], Some(&rest[i + 1..date_end])),
+            None => (&rest[..date_end], None)
+        };
+        let date = try!(self.parse_date(date_str, 1));
+        let date2 = match date2_str {
+            Some(d) => Some(try!(self.parse_date(d, date_str.len() + 2))),
+            None => None
+        };
+        rest = rest[date_end..].trim_left();
+
+        let (status, r) = parse_status(rest);
+        rest = r;
+
+        let mut code = String::new();
+        if rest.starts_with('(') {
+            match rest.find(')') {
+                Some(i) => {
+                    code = rest[1..i].trim().to_string();
+                    rest = rest[i + 1..].trim_left();
+                },
+                None => return Err(self.error(ParseErrorKind::UnexpectedInput(rest.to_string()), 1))
+            }
+        }
+
+        let mut comments: Vec<String> = comment.into_iter().map(|x| x.trim().to_string()).collect();
+        let preceding_comment_lines = self.take_comment_lines();
+        self.line += 1;
+        let postings = try!(self.parse_postings(&mut comments));
+
+        let index = self.journal.transactions.len() + 1;
+        let tags = comments.iter().flat_map(|x| parse_tags(x).into_iter()).collect();
+        let transaction = Transaction {
+            index: index,
+            source_pos: source_pos,
+            date: date,
+            date2: date2,
+            status: status,
+            code: code,
+            description: rest.to_string(),
+            comment: comments.join("\n"),
+            tags: tags,
+            postings: postings,
+            preceding_comment_lines: preceding_comment_lines
+        };
+        self.journal.add_transaction_mut(transaction.tie_knot());
+        Ok(())
+    }
+
+    fn parse_periodic_transaction(&mut self) -> Result<(), ParseError> {
+        let (header, _) = split_comment(self.lines[self.line]);
+        let expression = header[1..].trim().to_string();
+        let source_pos = self.source_pos(1);
+        self.line += 1;
+        let postings = try!(self.parse_postings(&mut Vec::new()));
+        self.journal.periodic_transactions.push(PeriodicTransaction {
+            periodic_expression: expression,
+            source_pos: source_pos,
+            postings: postings
+        });
+        Ok(())
+    }
+
+    fn parse_modifier_transaction(&mut self) -> Result<(), ParseError> {
+        let (header, _) = split_comment(self.lines[self.line]);
+        let expression = header[1..].trim().to_string();
+        self.line += 1;
+        let postings = try!(self.parse_postings(&mut Vec::new()));
+        self.journal.add_modifier_transaction_mut(ModifierTransaction {
+            value_expression: expression,
+            postings: postings
+        });
+        Ok(())
+    }
+
+    /// Parses the indented lines following a transaction header. Comment lines
+    /// before the first posting are appended to `comments`, later ones belong
+    /// to the posting they follow.
+    fn parse_postings(&mut self, comments: &mut Vec<String>) -> Result<Vec<Posting>, ParseError> {
+        let mut postings: Vec<Posting> = Vec::new();
+        while self.line < self.lines.len() {
+            let l = self.lines[self.line];
+            if !l.starts_with(|c: char| c == ' ' || c == '\t') || l.trim().is_empty() {
+                break;
+            }
+            let text = l.trim_left();
+            if text.starts_with(';') {
+                let comment = text[1..].trim().to_string();
+                match postings.last_mut() {
+                    Some(p) => {
+                        p.tags.extend(parse_tags(&comment));
+                        if !p.comment.is_empty() {
+                            p.comment.push('\n');
+                        }
+                        p.comment.push_str(&comment);
+                    },
+                    None => comments.push(comment)
+                }
+            } else {
+                let column = l.len() - text.len() + 1;
+                postings.push(try!(self.parse_posting(text, column)));
+            }
+            self.line += 1;
+        }
+        Ok(postings)
+    }
+
+    fn parse_posting(&self, text: &str, column: usize) -> Result<Posting, ParseError> {
+        let (body, comment) = split_comment(text);
+        let (status, rest) = parse_status(body.trim_right());
+
+        let account_end = rest.find("  ").into_iter().chain(rest.find('\t').into_iter()).min().unwrap_or(rest.len());
+        let account = rest[..account_end].trim_right();
+        let amount_text = rest[account_end..].trim();
+
+        let posting_type = if account.starts_with('(') && account.ends_with(')') {
+            PostingType::Virtual
+        } else if account.starts_with('[') && account.ends_with(']') {
+            PostingType::BalancedVirtual
+        } else {
+            PostingType::Regular
+        };
+        let account = if posting_type == PostingType::Regular { account } else { account[1..account.len() - 1].trim() };
+        if account.is_empty() {
+            return Err(self.error(ParseErrorKind::InvalidPosting(text.trim().to_string()), column));
+        }
+
+        let (amount_text, assertion_text) = match find_unquoted(amount_text, '=') {
+            Some(i) => (amount_text[..i].trim(), Some(amount_text[i + 1..].trim())),
+            None => (amount_text, None)
+        };
+
+        let amount = if amount_text.is_empty() {
+            MixedAmount(vec!())
+        } else {
+            MixedAmount(vec!(try!(self.parse_amount_with_price(amount_text, column))))
+        };
+        let balance_assertion = match assertion_text {
+            Some(a) => {
+                let total = a.starts_with('=');
+                let a = if total { &a[1..] } else { a };
+                let inclusive = a.starts_with('*');
+                let a = if inclusive { &a[1..] } else { a };
+                Some(BalanceAssertion {
+                    amount: try!(self.parse_amount(a, column)),
+                    total: total,
+                    inclusive: inclusive
+                })
+            },
+            None => None
+        };
+
+        let comment = comment.map(|x| x.trim().to_string()).unwrap_or(String::new());
+        let mut posting = Posting::new();
+        posting.status = status;
+        posting.account = self.journal.context.resolve_account(AccountName::new(account.to_string()));
+        posting.amount = amount;
+        posting.posting_type = posting_type;
+        posting.tags = parse_tags(&comment);
+        posting.comment = comment;
+        posting.balance_assertion = balance_assertion;
+        Ok(posting)
+    }
+
+    fn parse_amount_with_price(&self, text: &str, column: usize) -> Result<Amount, ParseError> {
+        match find_unquoted(text, '@') {
+            Some(i) => {
+                let mut amount = try!(self.parse_annotated_amount(&text[..i], column));
+                let price = if text[i + 1..].starts_with('@') {
+                    Price::TotalPrice(try!(self.parse_amount(&text[i + 2..], column)))
+                } else {
+                    Price::UnitPrice(try!(self.parse_amount(&text[i + 1..], column)))
+                };
+                amount.price = Rc::new(price);
+                Ok(amount)
+            },
+            None => self.parse_annotated_amount(text, column)
+        }
+    }
+
+    /// Parses an amount optionally followed by lot annotations: a unit
+    /// `{price}` or total `{{price}}`, a `[date]` and a `(note)`, in any order
+    fn parse_annotated_amount(&self, text: &str, column: usize) -> Result<Amount, ParseError> {
+        let start = ['{', '[', '('].iter().filter_map(|&c| find_unquoted(text, c)).min();
+        let i = match start {
+            Some(i) => i,
+            None => return self.parse_amount(text, column)
+        };
+        let mut amount = try!(self.parse_amount(&text[..i], column));
+        let invalid = || self.error(ParseErrorKind::InvalidAmount(text.trim().to_string()), column);
+        let mut lot = Lot::new();
+        let mut rest = text[i..].trim();
+        while !rest.is_empty() {
+            let (open, close) = if rest.starts_with("{{") {
+                ("{{", "}}")
+            } else {
+                match rest.chars().next() {
+                    Some('{') => ("{", "}"),
+                    Some('[') => ("[", "]"),
+                    Some('(') => ("(", ")"),
+                    _ => return Err(invalid())
+                }
+            };
+            let end = try!(rest.find(close).ok_or(invalid()));
+            let inner = rest[open.len()..end].trim();
+            match open {
+                "{{" => lot.price = Price::TotalPrice(try!(self.parse_amount(inner, column))),
+                "{" => lot.price = Price::UnitPrice(try!(self.parse_amount(inner, column))),
+                "[" => lot.date = Some(try!(self.parse_date(inner, column))),
+                _ => lot.note = Some(inner.to_string())
+            }
+            rest = rest[end + close.len()..].trim_left();
+        }
+        amount.lot = Some(Rc::new(lot));
+        Ok(amount)
+    }
+
+    /// Parses an amount, giving it the default commodity if it has none
+    fn parse_amount(&self, text: &str, column: usize) -> Result<Amount, ParseError> {
+        let mut amount = try!(parse_amount(text).map_err(|_| self.error(ParseErrorKind::InvalidAmount(text.trim().to_string()), column)));
+        if let Some(ref d) = self.journal.context.default_commodity {
+            if amount.commodity.symbol().is_empty() {
+                amount.commodity = d.commodity.clone();
+                amount.style = d.style.clone();
+            }
+        }
+        Ok(amount)
+    }
+
+    /// Parses a date, taking the year of partial dates from the context
+    fn parse_date(&self, text: &str, column: usize) -> Result<Date<Local>, ParseError> {
+        let year = self.journal.context.year.as_ref().map(|&Year(y)| y);
+        match parse_date(text, year) {
+            Some(d) => Ok(d),
+            None if year.is_none() && parse_date(text, Some(2000)).is_some() =>
+                Err(self.error(ParseErrorKind::MissingYear(text.to_string()), column)),
+            None => Err(self.error(ParseErrorKind::InvalidDate(text.to_string()), column))
+        }
+    }
+
+    /// Parses a directive line and the indented lines belonging to it.
+    /// Directives that aren't understood are skipped along with those lines.
+    fn parse_directive(&mut self) -> Result<(), ParseError> {
+        let line = self.lines[self.line];
+        let (body, comment) = split_comment(line);
+        let body = body.trim_right();
+        let keyword_end = body.find(char::is_whitespace).unwrap_or(body.len());
+        let (keyword, arg) = (&body[..keyword_end], body[keyword_end..].trim());
+        let comment = comment.map(|x| x.trim().to_string()).unwrap_or(String::new());
+        let invalid = self.error(ParseErrorKind::InvalidDirective(line.trim().to_string()), 1);
+        self.take_comment_lines();
+
+        match keyword {
+            "comment" if arg.is_empty() => {
+                while self.line < self.lines.len() && self.lines[self.line].trim_right() != "end comment" {
+                    self.line += 1;
+                }
+                self.line += 1;
+                return Ok(());
+            },
+            "account" if !arg.is_empty() => return self.parse_account_directive(arg, comment),
+            "commodity" if !arg.is_empty() => return self.parse_commodity_directive(arg),
+            "include" if !arg.is_empty() => try!(self.include(arg, body.len() - body[keyword_end..].trim_left().len() + 1)),
+            "alias" if !arg.is_empty() => {
+                let alias = try!(arg.parse().map_err(|e| self.error(ParseErrorKind::InvalidAlias(e), 1)));
+                self.journal.context.aliases.push(alias);
+            },
+            "end" if arg == "aliases" => self.journal.context.aliases.clear(),
+            "payee" if !arg.is_empty() => self.journal.declared_payees.push(arg.to_string()),
+            "tag" if !arg.is_empty() => self.journal.declared_tags.push(arg.to_string()),
+            "Y" | "year" => {
+                let year = try!(arg.parse().map_err(|_| invalid));
+                self.journal.context.year = Some(Year(year));
+            },
+            k if k.len() > 1 && k.starts_with('Y') && arg.is_empty() => {
+                let year = try!(k[1..].parse().map_err(|_| invalid));
+                self.journal.context.year = Some(Year(year));
+            },
+            "D" if !arg.is_empty() => {
+                let amount = try!(self.parse_amount(arg, keyword_end + 2));
+                self.journal.declared_commodity_styles.entry(amount.commodity.clone()).or_insert(amount.style.clone());
+                self.journal.context.default_commodity = Some(amount);
+            },
+            "P" => {
+                let date_end = arg.find(char::is_whitespace).unwrap_or(arg.len());
+                let date = try!(self.parse_date(&arg[..date_end], keyword_end + 2));
+                let rest = arg[date_end..].trim_left();
+                let (symbol, price) = match parse_commodity_symbol(rest) {
+                    Ok(Some((symbol, price))) if !price.trim().is_empty() => (symbol, price.trim()),
+                    _ => return Err(invalid)
+                };
+                let amount = try!(self.parse_amount(price, line.find(price).unwrap_or(0) + 1));
+                self.journal.add_market_price_mut(MarketPrice { date: date, commodity: Commodity::new(symbol), amount: amount });
+            },
+            "apply" if arg.starts_with("account ") => {
+                let account = AccountName::new(arg["account".len()..].trim().to_string());
+                self.journal.context.parent_accounts.push(account);
+            },
+            "end" if arg == "apply account" || arg == "apply" => {
+                if self.journal.context.parent_accounts.pop().is_none() {
+                    return Err(invalid);
+                }
+            },
+            _ => ()
+        }
+        self.line += 1;
+        self.take_subdirectives();
+        Ok(())
+    }
+
+    /// Parses the files matching `pattern` into the journal, in name order
+    fn include(&mut self, pattern: &str, column: usize) -> Result<(), ParseError> {
+        let chain = self.include_chain();
+        let failed = |reason: String| ParseErrorKind::IncludeFailed(pattern.to_string(), reason, chain.clone());
+        let base = Path::new(&self.file).parent().map(|x| x.to_path_buf()).unwrap_or(PathBuf::new());
+        let paths = try!(expand_glob(&base, pattern).map_err(|e| self.error(failed(e), column)));
+        for path in paths {
+            let name = path.to_string_lossy().into_owned();
+            let input = try!(read_file(&path).map_err(|e| self.error(failed(e), column)));
+            let canonical = try!(fs::canonicalize(&path).map_err(|e| self.error(failed(e.to_string()), column)));
+            if self.include_stack.iter().any(|&(ref p, _)| *p == canonical) {
+                let cycle = format!("{} -> {}", chain, name);
+                return Err(self.error(ParseErrorKind::IncludeCycle(cycle), column));
+            }
+
+            let journal = mem::replace(&mut self.journal, Journal::new());
+            let mut stack = self.include_stack.clone();
+            stack.push((canonical, name.clone()));
+            let mut parser = JournalParser::new(name, &input, journal, stack);
+            let r = parser.parse_lines();
+            let comments = parser.take_comment_lines();
+            self.journal = parser.journal;
+            try!(r);
+            if !comments.is_empty() {
+                self.comment_lines.push(comments);
+            }
+        }
+        Ok(())
+    }
+
+    /// The files being parsed, as written, e.g. `2016.journal -> bank.journal`
+    fn include_chain(&self) -> String {
+        let mut names: Vec<String> = self.include_stack.iter().map(|&(_, ref name)| name.clone()).collect();
+        if names.is_empty() {
+            names.push(self.file.clone());
+        }
+        names.join(" -> ")
+    }
+
+    /// Skips the indented lines following a directive, returning them trimmed
+    fn take_subdirectives(&mut self) -> Vec<String> {
+        let mut r = Vec::new();
+        while self.line < self.lines.len() {
+            let l = self.lines[self.line];
+            if !l.starts_with(|c: char| c == ' ' || c == '\t') || l.trim().is_empty() {
+                break;
+            }
+            r.push(l.trim().to_string());
+            self.line += 1;
+        }
+        r
+    }
+
+    /// Parses `account NAME  [TYPE]  ; comment`, where the type may also be
+    /// given as a `type:` tag, followed by indented comment lines
+    fn parse_account_directive(&mut self, arg: &str, comment: String) -> Result<(), ParseError> {
+        let source_pos = self.source_pos(1);
+        let name_end = arg.find("  ").into_iter().chain(arg.find('\t').into_iter()).min().unwrap_or(arg.len());
+        let account = self.journal.context.resolve_account(AccountName::new(arg[..name_end].trim().to_string()));
+        let type_code = arg[name_end..].trim();
+
+        self.line += 1;
+        let mut comments: Vec<String> = if comment.is_empty() { Vec::new() } else { vec!(comment) };
+        comments.extend(self.take_subdirectives().into_iter().filter(|x| x.starts_with(';')).map(|x| x[1..].trim().to_string()));
+        let tag_type = comments.iter()
+            .flat_map(|x| parse_tags(x).into_iter())
+            .find(|t| t.name() == "type")
+            .map(|t| t.value().to_string());
+        let account_type = match tag_type.as_ref().map(|x| x.as_str()).or(if type_code.is_empty() { None } else { Some(type_code) }) {
+            Some(t) => match AccountType::from_str(t) {
+                Some(t) => Some(t),
+                None => {
+                    self.line = source_pos.line() - 1;
+                    return Err(self.error(ParseErrorKind::InvalidDirective(format!("account {}", arg)), 1));
+                }
+            },
+            None => None
+        };
+        self.journal.declared_accounts.push(AccountDeclaration {
+            account: account,
+            account_type: account_type,
+            comment: comments.join("\n"),
+            source_pos: source_pos
+        });
+        Ok(())
+    }
+
+    /// Parses `commodity SAMPLE` such as `commodity $1,000.00`, or `commodity
+    /// SYMBOL` optionally followed by an indented `format SAMPLE` line. A
+    /// sample amount declares the commodity's display style.
+    fn parse_commodity_directive(&mut self, arg: &str) -> Result<(), ParseError> {
+        let column = self.lines[self.line].find(arg).unwrap_or(0) + 1;
+        let sample = if arg.chars().any(|c| c.is_digit(10)) {
+            Some(try!(self.parse_amount(arg, column)))
+        } else {
+            None
+        };
+        let commodity = match sample {
+            Some(ref a) => a.commodity.clone(),
+            None => match parse_commodity_symbol(arg) {
+                Ok(Some((symbol, ref r))) if r.trim().is_empty() => Commodity::new(symbol),
+                _ => return Err(self.error(ParseErrorKind::InvalidDirective(format!("commodity {}", arg)), 1))
+            }
+        };
+        if let Some(a) = sample {
+            self.journal.declared_commodity_styles.insert(commodity.clone(), a.style);
+        }
+
+        self.line += 1;
+        while self.line < self.lines.len() {
+            let l = self.lines[self.line];
+            if !l.starts_with(|c: char| c == ' ' || c == '\t') || l.trim().is_empty() {
+                break;
+            }
+            let (body, _) = split_comment(l.trim());
+            if body.starts_with("format") {
+                let text = body["format".len()..].trim();
+                let a = try!(self.parse_amount(text, l.find(text).unwrap_or(0) + 1));
+                if a.commodity != commodity {
+                    return Err(self.error(ParseErrorKind::InvalidDirective(format!("format {}", text)), 1));
+                }
+                self.journal.declared_commodity_styles.insert(commodity.clone(), a.style);
+            }
+            self.line += 1;
+        }
+        self.journal.declared_commodities.push(commodity);
+        Ok(())
+    }
+}
+
+/// Resolves an include pattern relative to `base`. Path components may
+/// contain `*` and `?` wildcards, which don't match a leading `.`, and `**`
+/// matches any number of directories. Matching files are returned sorted.
+fn expand_glob(base: &Path, pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let pattern = Path::new(pattern);
+    if !pattern.to_string_lossy().contains(|c| c == '*' || c == '?') {
+        return Ok(vec!(base.join(pattern)));
+    }
+    let mut paths = vec!(if pattern.is_absolute() { PathBuf::from("/") } else { base.to_path_buf() });
+    for component in pattern.iter() {
+        let component = component.to_string_lossy();
+        let mut next = Vec::new();
+        for dir in paths.iter() {
+            if component == "**" {
+                next.extend(subdirectories(dir));
+            } else if component.contains(|c| c == '*' || c == '?') {
+                let entries = match fs::read_dir(dir) {
+                    Ok(x) => x,
+                    Err(_) => continue
+                };
+                let mut matches: Vec<PathBuf> = entries
+                    .filter_map(|e| e.ok())
+                    .filter(|e| {
+                        let name = e.file_name().to_string_lossy().into_owned();
+                        (!name.starts_with('.') || component.starts_with('.')) && wildcard_match(&component, &name)
+                    })
+                    .map(|e| e.path())
+                    .collect();
+                matches.sort();
+                next.extend(matches);
+            } else if component != "/" {
+                next.push(dir.join(&*component));
+            }
+        }
+        paths = next;
+    }
+    let mut files: Vec<PathBuf> = paths.into_iter().filter(|x| x.is_file()).collect();
+    files.dedup();
+    if files.is_empty() {
+        Err(String::from("no files match"))
+    } else {
+        Ok(files)
+    }
+}
+
+/// A directory and everything below it, parents first
+fn subdirectories(dir: &Path) -> Vec<PathBuf> {
+    let mut r = vec!(dir.to_path_buf());
+    if let Ok(entries) = fs::read_dir(dir) {
+        let mut dirs: Vec<PathBuf> = entries.filter_map(|e| e.ok()).map(|e| e.path()).filter(|x| x.is_dir()).collect();
+        dirs.sort();
+        for d in dirs {
+            r.extend(subdirectories(&d));
+        }
+    }
+    r
+}
+
+/// Matches a file name against a pattern where `*` matches any run of
+/// characters and `?` any single character
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let n: Vec<char> = name.chars().collect();
+    let (mut i, mut j) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while j < n.len() {
+        if i < p.len() && (p[i] == '?' || p[i] == n[j]) {
+            i += 1;
+            j += 1;
+        } else if i < p.len() && p[i] == '*' {
+            star = Some((i, j));
+            i += 1;
+        } else if let Some((si, sj)) = star {
+            i = si + 1;
+            j = sj + 1;
+            star = Some((si, sj + 1));
+        } else {
+            return false;
+        }
+    }
+    p[i..].iter().all(|&c| c == '*')
+}
+
+/// Splits a line into its content and the comment following the first `;`
+fn split_comment(line: &str) -> (&str, Option<&str>) {
+    match find_unquoted(line, ';') {
+        Some(i) => (&line[..i], Some(&line[i + 1..])),
+        None => (line, None)
+    }
+}
+
+/// Finds the first occurrence of `c` outside of a double-quoted commodity symbol
+fn find_unquoted(text: &str, c: char) -> Option<usize> {
+    let mut quoted = false;
+    for (i, x) in text.char_indices() {
+        if x == '"' {
+            quoted = !quoted;
+        } else if x == c && !quoted {
+            return Some(i);
+        }
+    }
+    None
+}
+
+fn parse_status(text: &str) -> (ClearedStatus, &str) {
+    if text.starts_with('*') {
+        (ClearedStatus::Cleared, text[1..].trim_left())
+    } else if text.starts_with('!') {
+        (ClearedStatus::Pending, text[1..].trim_left())
+    } else {
+        (ClearedStatus::Uncleared, text)
+    }
+}
+
+/// Parses a full (`2016/01/31`) or partial (`01/31`) date. `/`, `-` and `.`
+/// are accepted as separators; partial dates take their year from
+/// `default_year` and are rejected without one.
+pub fn parse_date(text: &str, default_year: Option<i32>) -> Option<Date<Local>> {
+    let sep = match text.chars().find(|&c| c == '/' || c == '-' || c == '.') {
+        Some(c) => c,
+        None => return None
+    };
+    let parts: Vec<&str> = text.split(sep).collect();
+    if parts.iter().any(|x| x.is_empty() || !x.chars().all(|c| c.is_digit(10))) {
+        return None;
+    }
+    let (y, m, d) = match parts.as_slice() {
+        [ref y, ref m, ref d] => (y.parse().ok(), m.parse().ok(), d.parse().ok()),
+        [ref m, ref d] => (default_year, m.parse().ok(), d.parse().ok()),
+        _ => return None
+    };
+    match (y, m, d) {
+        (Some(y), Some(m), Some(d)) => Local.ymd_opt(y, m, d).single(),
+        _ => None
+    }
+}
+
+/// Parses the tags found in a comment. A tag is a word immediately followed
+/// by a colon; its value extends up to the next comma.
+pub fn parse_tags(comment: &str) -> Vec<Tag> {
+    let mut tags = Vec::new();
+    for segment in comment.split(',') {
+        if let Some(i) = segment.find(':') {
+            let name = segment[..i].split_whitespace().last().unwrap_or("");
+            if !name.is_empty() && !segment[..i].ends_with(char::is_whitespace) {
+                tags.push(Tag::new(name.to_string(), segment[i + 1..].trim().to_string()));
+            }
+        }
+    }
+    tags
+}
+
+/// Whether `c` may appear in a commodity symbol that isn't quoted
+pub fn is_commodity_char(c: char) -> bool {
+    !(c.is_digit(10) || c.is_whitespace() || "-+.,@*;\"{}=()[]".contains(c))
+}
+
+/// Parses a single amount such as `$-1,000.00`, `-10 EUR` or `"AAPL 2" 3`,
+/// recording the way it was written in the amount's style.
+pub fn parse_amount(text: &str) -> Result<Amount, String> {
+    let mut rest = text.trim();
+    let mut negative = false;
+    if rest.starts_with('-') || rest.starts_with('+') {
+        negative = rest.starts_with('-');
+        rest = rest[1..].trim_left();
+    }
+
+    let mut style = AmountStyle::new();
+    let left = try!(parse_commodity_symbol(rest));
+    let commodity;
+    let number;
+    match left {
+        Some((symbol, r)) => {
+            style.commodity_side = Side::Left;
+            style.commodity_spaced = r.starts_with(char::is_whitespace);
+            let mut r = r.trim_left();
+            if r.starts_with('-') || r.starts_with('+') {
+                negative = r.starts_with('-');
+                r = &r[1..];
+            }
+            if !r.trim().chars().all(|c| c.is_digit(10) || c == '.' || c == ',') {
+                return Err(text.to_string());
+            }
+            commodity = symbol;
+            number = r.trim();
+        },
+        None => {
+            let end = rest.find(|c: char| !(c.is_digit(10) || c == '.' || c == ',')).unwrap_or(rest.len());
+            number = &rest[..end];
+            let r = &rest[end..];
+            style.commodity_side = Side::Right;
+            style.commodity_spaced = r.starts_with(char::is_whitespace);
+            commodity = match try!(parse_commodity_symbol(r.trim_left())) {
+                Some((symbol, r)) if r.trim().is_empty() => symbol,
+                None if r.trim().is_empty() => String::new(),
+                _ => return Err(text.to_string())
+            };
+        }
+    }
+
+    let (quantity, decimal_point, digit_groups) = try!(parse_number(number).ok_or(text.to_string()));
+    style.precision = quantity.scale as usize;
+    style.decimal_point = decimal_point;
+    style.digit_groups = digit_groups;
+    Ok(Amount {
+        commodity: Commodity::new(commodity),
+        quantity: Quantity(if negative { quantity * -1 } else { quantity }),
+        price: Rc::new(Price::None),
+        lot: None,
+        style: style
+    })
+}
+
+/// Reads a commodity symbol from the start of `text`, either quoted or as a
+/// run of symbol characters, and returns it together with the remaining text
+fn parse_commodity_symbol(text: &str) -> Result<Option<(String, &str)>, String> {
+    if text.starts_with('"') {
+        match text[1..].find('"') {
+            Some(i) => Ok(Some((text[1..i + 1].to_string(), &text[i + 2..]))),
+            None => Err(text.to_string())
+        }
+    } else {
+        let end = text.find(|c: char| !is_commodity_char(c)).unwrap_or(text.len());
+        if end == 0 {
+            Ok(None)
+        } else {
+            Ok(Some((text[..end].to_string(), &text[end..])))
+        }
+    }
+}
+
+/// Parses the digits of an amount, detecting its decimal mark and digit group
+/// separator. A number with a single `,` followed by exactly three digits is
+/// taken to use digit grouping; otherwise a lone `.` or `,` is a decimal mark.
+fn parse_number(text: &str) -> Option<(Decimal, Option<char>, Option<DigitGroupStyle>)> {
+    if text.is_empty() || !text.chars().any(|c| c.is_digit(10)) {
+        return None;
+    }
+    let marks: Vec<(usize, char)> = text.char_indices().filter(|&(_, c)| !c.is_digit(10)).collect();
+    let last = marks.last().cloned();
+    let decimal_mark = match last {
+        None => None,
+        Some((i, c)) => {
+            let count = marks.iter().filter(|&&(_, x)| x == c).count();
+            let mixed = marks.iter().any(|&(_, x)| x != c);
+            if mixed {
+                if count > 1 {
+                    return None;
+                }
+                Some((i, c))
+            } else if count > 1 || (c == ',' && text.len() - i - 1 == 3) {
+                None
+            } else {
+                Some((i, c))
+            }
+        }
+    };
+
+    let int_part = match decimal_mark {
+        Some((i, _)) => &text[..i],
+        None => text
+    };
+    let group_char = int_part.chars().find(|c| !c.is_digit(10));
+    let digit_groups = match group_char {
+        Some(g) => {
+            let groups: Vec<&str> = int_part.split(g).collect();
+            if groups.iter().any(|x| x.is_empty()) {
+                return None;
+            }
+            Some(DigitGroupStyle(g, groups.iter().skip(1).rev().map(|x| x.len()).collect()))
+        },
+        None => None
+    };
+
+    let format = DecimalFormat {
+        decimal_mark: decimal_mark.map_or(if group_char == Some('.') { ',' } else { '.' }, |(_, c)| c),
+        group_separator: group_char,
+        allow_exponent: false
+    };
+    match Decimal::parse_with(text, &format) {
+        Ok(d) => Some((d, decimal_mark.map(|(_, c)| c), digit_groups)),
+        Err(_) => None
+    }
+}
+
+#[test]
+fn parse_transaction_test() {
+    let journal = parse_journal("; opening\n2016/01/02=2016/01/05 * (42) Grocery store  ; food:yes\n    ; note\n    expenses:food    $10.50\n    ! assets:cash  ; paid:cash\n").unwrap();
+    assert!(journal.transactions.len() == 1);
+    let t = &journal.transactions[0];
+    assert!(t.date == Local.ymd(2016, 1, 2));
+    assert!(t.date2 == Some(Local.ymd(2016, 1, 5)));
+    assert!(t.status == ClearedStatus::Cleared);
+    assert!(t.code == "42");
+    assert!(t.description == "Grocery store");
+    assert!(t.comment == "food:yes\nnote");
+    assert!(t.preceding_comment_lines == "; opening");
+    assert!(t.source_pos == GenericSourcePos::new(String::new(), 2, 1));
+    assert!(t.postings.len() == 2);
+    assert!(t.postings[0].account == AccountName::new(String::from("expenses:food")));
+    assert!(t.postings[0].amount.0[0].quantity == Quantity(Decimal::new(1050, 2)));
+    assert!(t.postings[1].amount.0.is_empty());
+    assert!(t.postings[1].status() == ClearedStatus::Pending);
+    assert!(t.postings[1].tags == vec!(Tag::new(String::from("paid"), String::from("cash"))));
+}
+
+#[test]
+fn parse_amount_test() {
+    let a = parse_amount("-1.234,50 EUR").unwrap();
+    assert!(a.commodity == Commodity::new(String::from("EUR")));
+    assert!(a.quantity == Quantity(Decimal::new(-123450, 2)));
+    assert!(a.style.commodity_side == Side::Right && a.style.commodity_spaced);
+    assert!(a.style.decimal_point == Some(','));
+    assert!(a.style.digit_groups == Some(DigitGroupStyle('.', vec!(3))));
+
+    let b = parse_amount("$-5").unwrap();
+    assert!(b.quantity == Quantity(Decimal::new(-5, 0)));
+    assert!(b.style.commodity_side == Side::Left && !b.style.commodity_spaced);
+
+    let c = parse_amount("1.000.000 EUR").unwrap();
+    assert!(c.quantity == Quantity(Decimal::new(1000000, 0)));
+    assert!(c.style.digit_groups == Some(DigitGroupStyle('.', vec!(3, 3))));
+    assert!(parse_amount("EUR 1.000.000").unwrap().quantity == Quantity(Decimal::new(1000000, 0)));
+
+    assert!(parse_amount("1..2 USD").is_err());
+}
+
+#[test]
+fn parse_error_test() {
+    let err = parse_journal("2016/01/01 ok\n    a  1\n    b\n2016/13/01 bad\n").err().unwrap();
+    assert!(err.kind == ParseErrorKind::InvalidDate(String::from("2016/13/01")));
+    assert!(err.source_pos.line() == 4);
+
+    let partial = "03/05 x\n    a  1\n    b\n";
+    let err = parse_journal(partial).err().unwrap();
+    assert!(err.kind == ParseErrorKind::MissingYear(String::from("03/05")));
+    let mut context = JournalContext::new();
+    context.year = Some(Year(2014));
+    let journal = parse_journal_file_with_context(String::new(), partial, context).unwrap();
+    assert!(journal.transactions[0].date == Local.ymd(2014, 3, 5));
+}
+
+#[test]
+fn parse_directives_test() {
+    use amount::Side;
+    let journal = parse_journal("Y 2015\n\
+                                 account assets:bank  A  ; main account\n    ; opened 2010\n\
+                                 account expenses:food  ; type: X\n\
+                                 commodity EUR\n    format 1.000,00 EUR\n\
+                                 commodity $1,000.00\n\
+                                 payee Grocery store\n\
+                                 tag trip\n\
+                                 D 1,000.00 GBP\n\
+                                 P 2015/01/01 EUR $1.10\n\
+                                 apply account personal\n\
+                                 03/05 shopping\n    expenses:food  12.5\n    assets:bank\n\
+                                 end apply account\n\
+                                 2016/01/01 x\n    a  1 EUR\n    b\n").unwrap();
+
+    assert!(journal.declared_accounts.len() == 2);
+    assert!(journal.declared_accounts[0].account == AccountName::new(String::from("assets:bank")));
+    assert!(journal.declared_accounts[0].account_type == Some(AccountType::Asset));
+    assert!(journal.declared_accounts[0].comment == "main account\nopened 2010");
+    assert!(journal.declared_accounts[1].account_type == Some(AccountType::Expense));
+    assert!(journal.declared_commodities == vec!(Commodity::new(String::from("EUR")), Commodity::new(String::from("$"))));
+    let eur = &journal.declared_commodity_styles[&Commodity::new(String::from("EUR"))];
+    assert!(eur.decimal_point == Some(',') && eur.commodity_side == Side::Right && eur.precision == 2);
+    assert!(journal.declared_payees == vec!(String::from("Grocery store")));
+    assert!(journal.declared_tags == vec!(String::from("trip")));
+    assert!(journal.market_prices.len() == 1 && journal.market_prices[0].amount.commodity == Commodity::new(String::from("$")));
+
+    let t = &journal.transactions[0];
+    assert!(t.date == Local.ymd(2015, 3, 5));
+    assert!(t.postings[0].account == AccountName::new(String::from("personal:expenses:food")));
+    assert!(format!("{}", t.postings[0].amount) == "12.50 GBP");
+    assert!(journal.transactions[1].postings[0].account == AccountName::new(String::from("a")));
+
+    let err = parse_journal("end apply account\n").err().unwrap();
+    assert!(err.kind == ParseErrorKind::InvalidDirective(String::from("end apply account")));
+    assert!(parse_journal("account a  Z\n").is_err());
+
+    let skipped = parse_journal("comment\n2016/01/01 hidden\n    a  1\n    b\nend comment\n\
+                                 decimal-mark ,\n\
+                                 assert something\n    with details\n\
+                                 2016/01/02 shown\n    a  1\n    b\n").unwrap();
+    assert!(skipped.transactions.len() == 1 && skipped.transactions[0].description == "shown");
+    assert!(parse_journal("comment\n2016/01/01 hidden\n    a  1\n    b\n").unwrap().transactions.is_empty());
+}
+
+#[test]
+fn include_test() {
+    use std::env;
+    use std::io::Write;
+    use chrono::Timelike;
+
+    /// Removes the test directory even when an assertion fails
+    struct TempDir(PathBuf);
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    let now = Local::now();
+    let temp = (0..).map(|n| env::temp_dir().join(format!("rledger-include-test-{}-{}-{}", now.timestamp(), now.nanosecond(), n)))
+        .find(|d| fs::create_dir(d).is_ok())
+        .map(TempDir)
+        .unwrap();
+    let dir = temp.0.clone();
+    let write = |name: &str, content: &str| {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::File::create(path).unwrap().write_all(content.as_bytes()).unwrap();
+    };
+    write("main.journal", "include banks/*.journal\n\n2016/03/01 main\n    a  $1\n    b\n");
+    write("banks/b.journal", "2016/02/01 b\n    a  $1\n    b\n");
+    write("banks/a.journal", "apply account bank\n\n2016/01/01 a\n    a  $1\n    b\nend apply account\ninclude ../more/c.journal\n");
+    write("more/c.journal", "2016/01/15 c\n    a  $1\n    b\n");
+    write("cycle1.journal", "include cycle2.journal\n");
+    write("cycle2.journal", "\ninclude cycle1.journal\n");
+    write("missing.journal", "include nothing.journal\n");
+
+    let journal = read_journal_file(dir.join("main.journal"));
+    let cycle = read_journal_file(dir.join("cycle1.journal"));
+    let missing = read_journal_file(dir.join("missing.journal"));
+
+    let journal = journal.unwrap();
+    let descriptions: Vec<&str> = journal.transactions.iter().map(|t| t.description.as_ref()).collect();
+    assert_eq!(descriptions, vec!("a", "c", "b", "main"));
+    assert!(journal.transactions[0].source_pos.file().ends_with("a.journal") && journal.transactions[0].source_pos.line() == 3);
+    assert!(journal.transactions[0].postings[0].account == AccountName::new(String::from("bank:a")));
+    assert!(journal.transactions[1].source_pos.file().ends_with("c.journal"));
+    assert!(journal.transactions[3].source_pos.line() == 3 && journal.transactions[3].index == 4);
+
+    let cycle = cycle.err().unwrap();
+    assert!(cycle.source_pos.file().ends_with("cycle2.journal") && cycle.source_pos.line() == 2 && cycle.source_pos.column() == 9);
+    let name = |file: &str| dir.join(file).to_string_lossy().into_owned();
+    match cycle.kind {
+        ParseErrorKind::IncludeCycle(ref chain) =>
+            assert_eq!(*chain, format!("{} -> {} -> {}", name("cycle1.journal"), name("cycle2.journal"), name("cycle1.journal"))),
+        _ => panic!("expected an include cycle")
+    }
+    match missing.err().unwrap().kind {
+        ParseErrorKind::IncludeFailed(ref file, _, ref chain) => assert!(file == "nothing.journal" && *chain == name("missing.journal")),
+        _ => panic!("expected a missing include")
+    }
+}
+
+#[test]
+fn alias_test() {
+    use account_name::AccountAlias;
+    let mut journal = parse_journal("alias checking = assets:bank:checking\n\
+                                     alias /^(assets:bank):(\\w+)$/ = \\1:old:\\2\n\
+                                     apply account personal\n\
+                                     2016/01/01 a\n    checking  $1\n    equity\n\
+                                     end apply account\n\
+                                     2016/01/02 b\n    checking  $1\n    assets:bank:savings\n\
+                                     end aliases\n\
+                                     2016/01/03 c\n    checking  $1\n    equity\n").unwrap();
+    let accounts = |j: &Journal| j.postings().iter().map(|p| p.account.as_str().to_string()).collect::<Vec<String>>();
+    assert_eq!(accounts(&journal), vec!("personal:checking", "personal:equity",
+                                        "assets:bank:checking", "assets:bank:old:savings",
+                                        "checking", "equity"));
+
+    let alias: AccountAlias = "equity = equity:opening".parse().unwrap();
+    journal.apply_account_aliases(&[alias]);
+    assert_eq!(accounts(&journal)[5], "equity:opening");
+    assert!(journal.transactions[2].postings[1].transaction.is_some());
+    let err = parse_journal("alias /x = y\n").err().unwrap();
+    assert!(err.kind == ParseErrorKind::InvalidAlias(String::from("unterminated regular expression in alias '/x = y'")));
+}
@@ -1,5 +1,7 @@
-use amount::MixedAmount;
+use account_name::AccountName;
+use amount::{ Amount, MixedAmount };
 use transaction::Transaction;
+use chrono::{ Date, Local };
 use std::fmt::Display;
 use std::fmt::Formatter;
 use std::fmt::Error;
@@ -25,6 +27,30 @@ impl Display for ClearedStatus {
 #[derive(Clone, PartialEq, Eq)]
 pub struct Tag(String, String);
 
+impl Tag {
+    pub fn new(name: String, value: String) -> Tag {
+        Tag(name, value)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+
+    pub fn value(&self) -> &str {
+        &self.1
+    }
+}
+
+/// A balance assertion written after a posting's amount. `=` checks only the
+/// asserted commodity, `==` the account's whole balance; a trailing `*`
+/// includes the balances of subaccounts.
+#[derive(Clone, PartialEq, Eq)]
+pub struct BalanceAssertion {
+    pub amount: Amount,
+    pub total: bool,
+    pub inclusive: bool
+}
+
 #[derive(Clone, PartialEq, Eq)]
 pub enum PostingType {
     Regular,
@@ -35,14 +61,32 @@ pub enum PostingType {
 #[derive(Clone, PartialEq, Eq)]
 pub struct Posting {
     pub status: ClearedStatus,
+    pub account: AccountName,
     pub amount: MixedAmount,
+    pub comment: String,
     pub posting_type: PostingType,
     pub tags: Vec<Tag>,
-    pub balance_assertion: Option<MixedAmount>,
+    pub balance_assertion: Option<BalanceAssertion>,
+    /// Whether the amount was inferred while balancing rather than written
+    pub amount_inferred: bool,
     pub transaction: Option<Transaction>
 }
 
 impl Posting {
+    pub fn new() -> Posting {
+        Posting {
+            status: ClearedStatus::Uncleared,
+            account: AccountName::new(String::new()),
+            amount: MixedAmount(vec!()),
+            comment: String::new(),
+            posting_type: PostingType::Regular,
+            tags: Vec::new(),
+            balance_assertion: None,
+            amount_inferred: false,
+            transaction: None
+        }
+    }
+
     pub fn is_real(&self) -> bool {
         self.posting_type == PostingType::Regular
     }
@@ -72,6 +116,16 @@ impl Posting {
         }
     }
 
+    /// The date of the posting's transaction, if it belongs to one
+    pub fn date(&self) -> Option<Date<Local>> {
+        self.transaction.as_ref().map(|t| t.date)
+    }
+
+    /// The secondary date of the posting's transaction, falling back to its primary date
+    pub fn date2(&self) -> Option<Date<Local>> {
+        self.transaction.as_ref().map(|t| t.date2.unwrap_or(t.date))
+    }
+
     pub fn all_tags(&self) -> Vec<Tag> {
         self.tags.iter().chain(match self.transaction.clone() {
             Some(t) => t.tags,
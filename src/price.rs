@@ -1,8 +1,145 @@
-use amount::Amount;
+use std::collections::{ BTreeMap, VecDeque };
+use chrono::{ Date, Local };
+use amount::{ Amount, AmountStyle };
+use commodity::Commodity;
+use decimal::Decimal;
+use journal::MarketPrice;
 
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Price {
     None,
     UnitPrice(Amount),
     TotalPrice(Amount)
 }
+
+/// Number of decimal places kept when a price is inverted
+pub const INVERSE_PRICE_SCALE: u32 = 10;
+
+/// Market prices indexed by commodity pair, each list sorted by date, and
+/// the display style of each commodity that values are converted into
+#[derive(Clone, PartialEq, Eq)]
+pub struct PriceDb {
+    prices: BTreeMap<(Commodity, Commodity), Vec<(Date<Local>, Decimal)>>,
+    styles: BTreeMap<Commodity, AmountStyle>
+}
+
+impl PriceDb {
+    pub fn new() -> PriceDb {
+        PriceDb { prices: BTreeMap::new(), styles: BTreeMap::new() }
+    }
+
+    /// Takes each target commodity's style from the last price given in it
+    pub fn from_market_prices(market_prices: &Vec<MarketPrice>) -> PriceDb {
+        let mut db = PriceDb::new();
+        for p in market_prices {
+            db.add(p.date, p.commodity.clone(), p.amount.commodity.clone(), p.amount.quantity.0);
+            db.set_style(p.amount.commodity.clone(), p.amount.style.clone());
+        }
+        db
+    }
+
+    /// The style in which amounts converted into `commodity` are shown
+    pub fn style(&self, commodity: &Commodity) -> Option<&AmountStyle> {
+        self.styles.get(commodity)
+    }
+
+    pub fn set_style(&mut self, commodity: Commodity, style: AmountStyle) {
+        self.styles.insert(commodity, style);
+    }
+
+    /// Records that one unit of `from` costs `price` units of `to` from `date` on.
+    /// A later entry for the same pair and date replaces the earlier one.
+    pub fn add(&mut self, date: Date<Local>, from: Commodity, to: Commodity, price: Decimal) {
+        let entries = self.prices.entry((from, to)).or_insert(Vec::new());
+        let pos = entries.iter().position(|&(d, _)| d > date).unwrap_or(entries.len());
+        if pos > 0 && entries[pos - 1].0 == date {
+            entries[pos - 1].1 = price;
+        } else {
+            entries.insert(pos, (date, price));
+        }
+    }
+
+    /// The latest price declared for `from` in `to` on or before `date`
+    pub fn direct_rate(&self, from: &Commodity, to: &Commodity, date: &Date<Local>) -> Option<Decimal> {
+        self.prices.get(&(from.clone(), to.clone()))
+            .and_then(|entries| entries.iter().rev().find(|&&(d, _)| d <= *date))
+            .map(|&(_, p)| p)
+    }
+
+    /// The commodity in which `from` was most recently priced on or before `date`
+    pub fn default_target(&self, from: &Commodity, date: &Date<Local>) -> Option<Commodity> {
+        self.prices.iter()
+            .filter(|&(&(ref a, _), _)| a == from)
+            .filter_map(|(&(_, ref b), entries)| entries.iter().rev().find(|&&(d, _)| d <= *date).map(|&(d, _)| (d, b.clone())))
+            .max_by_key(|&(d, _)| d)
+            .map(|(_, b)| b)
+    }
+
+    /// The commodities that `from` can be converted to directly on `date`, using
+    /// declared prices first and inverted prices for the remaining ones
+    fn neighbours(&self, from: &Commodity, date: &Date<Local>) -> Vec<(Commodity, Decimal)> {
+        let mut r: Vec<(Commodity, Decimal)> = Vec::new();
+        for &(ref a, ref b) in self.prices.keys() {
+            if a == from {
+                if let Some(p) = self.direct_rate(a, b, date) {
+                    r.push((b.clone(), p));
+                }
+            }
+        }
+        for &(ref a, ref b) in self.prices.keys() {
+            if b == from && !r.iter().any(|&(ref c, _)| c == a) {
+                if let Some(p) = self.direct_rate(a, b, date) {
+                    if !p.is_zero() {
+                        r.push((a.clone(), Decimal::new(1, 0).div_with_scale(&p, INVERSE_PRICE_SCALE).normalize()));
+                    }
+                }
+            }
+        }
+        r
+    }
+
+    /// The price of one unit of `from` in `to` on `date`. Uses a declared
+    /// price if there is one, otherwise an inverted price, otherwise the
+    /// shortest chain of prices through other commodities.
+    pub fn rate(&self, from: &Commodity, to: &Commodity, date: &Date<Local>) -> Option<Decimal> {
+        if from == to {
+            return Some(Decimal::new(1, 0));
+        }
+        let mut visited = vec!(from.clone());
+        let mut queue = VecDeque::new();
+        queue.push_back((from.clone(), Decimal::new(1, 0)));
+        while let Some((c, rate)) = queue.pop_front() {
+            for (n, p) in self.neighbours(&c, date) {
+                if visited.contains(&n) {
+                    continue;
+                }
+                let r = (rate * p).normalize();
+                if n == *to {
+                    return Some(r);
+                }
+                visited.push(n.clone());
+                queue.push_back((n, r));
+            }
+        }
+        None
+    }
+}
+
+#[test]
+fn price_db_test() {
+    use chrono::TimeZone;
+    let c = |s: &str| Commodity::new(String::from(s));
+    let mut db = PriceDb::new();
+    db.add(Local.ymd(2016, 1, 1), c("EUR"), c("USD"), Decimal::new(110, 2));
+    db.add(Local.ymd(2016, 2, 1), c("EUR"), c("USD"), Decimal::new(125, 2));
+    db.add(Local.ymd(2016, 1, 15), c("AAPL"), c("USD"), Decimal::new(100, 0));
+    db.add(Local.ymd(2016, 1, 1), c("BTC"), c("AAPL"), Decimal::new(4, 0));
+
+    assert_eq!(db.rate(&c("EUR"), &c("USD"), &Local.ymd(2016, 1, 31)), Some(Decimal::new(11, 1)));
+    assert_eq!(db.rate(&c("EUR"), &c("USD"), &Local.ymd(2016, 2, 1)), Some(Decimal::new(125, 2)));
+    assert_eq!(db.rate(&c("EUR"), &c("USD"), &Local.ymd(2015, 12, 31)), None);
+    assert_eq!(db.rate(&c("USD"), &c("EUR"), &Local.ymd(2016, 3, 1)), Some(Decimal::new(8, 1)));
+    assert_eq!(db.rate(&c("BTC"), &c("EUR"), &Local.ymd(2016, 3, 1)), Some(Decimal::new(320, 0)));
+    assert_eq!(db.rate(&c("BTC"), &c("EUR"), &Local.ymd(2016, 1, 2)), None);
+    assert_eq!(c("EUR").conversion_rate(&c("EUR"), &Local.ymd(2000, 1, 1), &db), Some(Decimal::new(1, 0)));
+}
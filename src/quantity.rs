@@ -1,13 +1,45 @@
-use std::ops::Mul;
+use std::ops::{ Add, Div, Mul, Neg, Sub };
 use decimal::Decimal;
 
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub struct Quantity(pub Decimal);
 
 impl Quantity {
     pub fn abs(self) -> Quantity {
         Quantity(self.0.abs())
     }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.0.is_negative()
+    }
+}
+
+impl Add for Quantity {
+    type Output = Quantity;
+
+    fn add(self, Quantity(r): Quantity) -> Quantity {
+        Quantity(self.0 + r)
+    }
+}
+
+impl Sub for Quantity {
+    type Output = Quantity;
+
+    fn sub(self, Quantity(r): Quantity) -> Quantity {
+        Quantity(self.0 - r)
+    }
+}
+
+impl Neg for Quantity {
+    type Output = Quantity;
+
+    fn neg(self) -> Quantity {
+        Quantity(-self.0)
+    }
 }
 
 impl Mul for Quantity {
@@ -17,3 +49,11 @@ impl Mul for Quantity {
         Quantity(self.0 * r)
     }
 }
+
+impl Div for Quantity {
+    type Output = Quantity;
+
+    fn div(self, Quantity(r): Quantity) -> Quantity {
+        Quantity(self.0 / r)
+    }
+}
@@ -1,10 +1,18 @@
 use std::rc::Rc;
-use chrono::{ Date, Duration, Local };
+use std::fmt;
+use std::error;
+use std::str::FromStr;
+use chrono::{ Date, Local };
 use regex::Regex;
 
 use posting::ClearedStatus;
 use quantity::Quantity;
+use decimal::Decimal;
 use amount::{ Amount, MixedAmount };
+use account_name::AccountName;
+use posting::{ Posting, Tag };
+use transaction::Transaction;
+use dates::{ self, DateSpan };
 
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub enum OrdPlus {
@@ -30,8 +38,8 @@ pub enum Query {
     Code(Regex),
     Desc(Regex),
     Acct(Regex),
-    Date(Duration),
-    Date2(Duration),
+    Date(DateSpan),
+    Date2(DateSpan),
     Status(ClearedStatus),
     Real(bool),
     Amount(OrdPlus, Quantity),
@@ -47,8 +55,178 @@ pub enum QueryOption {
     InAccount(String)
 }
 
-pub fn parse_query(day: Date<Local>, query: String) -> (Query, Vec<QueryOption>) {
-    (Query::None, Vec::new())
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseQueryError {
+    desc: String,
+}
+
+impl ParseQueryError {
+    fn new(desc: String) -> Self {
+        ParseQueryError { desc: desc }
+    }
+}
+
+impl fmt::Display for ParseQueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.desc.fmt(f)
+    }
+}
+
+impl error::Error for ParseQueryError {
+    fn description(&self) -> &str {
+        &self.desc
+    }
+}
+
+/// Parses a query string in hledger syntax into a query and any query options.
+///
+/// Multiple account terms are or-ed together, as are multiple description and
+/// multiple status terms; everything else is and-ed.
+pub fn parse_query(day: Date<Local>, query: String) -> Result<(Query, Vec<QueryOption>), ParseQueryError> {
+    let mut descs = Vec::new();
+    let mut accts = Vec::new();
+    let mut statuses = Vec::new();
+    let mut others = Vec::new();
+    let mut opts = Vec::new();
+
+    for word in split_query_words(&query) {
+        match try!(parse_query_term(&day, &word)) {
+            Ok(q @ Query::Desc(_)) => descs.push(q),
+            Ok(q @ Query::Acct(_)) => accts.push(q),
+            Ok(q @ Query::Status(_)) => statuses.push(q),
+            Ok(q) => others.push(q),
+            Err(opt) => opts.push(opt)
+        }
+    }
+
+    let mut qs: Vec<Query> = vec!(accts, descs, statuses).into_iter().filter(|x| !x.is_empty()).map(|mut x| {
+        if x.len() == 1 { x.remove(0) } else { Query::Or(x) }
+    }).collect();
+    qs.extend(others);
+
+    let q = match qs.len() {
+        0 => Query::Any,
+        1 => qs.remove(0),
+        _ => Query::And(qs)
+    };
+    Ok((q, opts))
+}
+
+/// Splits a query string on whitespace, keeping single- or double-quoted
+/// parts (which may follow a prefix, as in `desc:'my shop'`) together
+fn split_query_words(query: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quote: Option<char> = None;
+    let mut in_word = false;
+    for c in query.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => word.push(c),
+            None if c == '\'' || c == '"' => {
+                quote = Some(c);
+                in_word = true;
+            },
+            None if c.is_whitespace() => {
+                if in_word {
+                    words.push(word.clone());
+                    word.clear();
+                    in_word = false;
+                }
+            },
+            None => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    words
+}
+
+fn query_regex(pattern: &str) -> Result<Regex, ParseQueryError> {
+    Regex::new(&format!("(?i){}", pattern)).map_err(|e| ParseQueryError::new(format!("invalid regular expression '{}': {}", pattern, e)))
+}
+
+fn parse_bool(s: &str) -> Result<bool, ParseQueryError> {
+    match s {
+        "" | "1" | "t" | "true" => Ok(true),
+        "0" | "f" | "false" => Ok(false),
+        _ => Err(ParseQueryError::new(format!("unknown boolean '{}', expected 1, 0, t, f, true, false or nothing", s)))
+    }
+}
+
+/// Parses a single query term into either a query or a query option
+fn parse_query_term(day: &Date<Local>, term: &str) -> Result<Result<Query, QueryOption>, ParseQueryError> {
+    if term.starts_with("not:") {
+        return match try!(parse_query_term(day, &term[4..])) {
+            Ok(q) => Ok(Ok(Query::Not(Rc::new(q)))),
+            Err(_) => Err(ParseQueryError::new(format!("cannot negate query option '{}'", &term[4..])))
+        };
+    }
+
+    let (prefix, value) = match term.find(':') {
+        Some(i) => (&term[..i + 1], &term[i + 1..]),
+        None => ("", term)
+    };
+    let q = match prefix {
+        "inacctonly:" => return Ok(Err(QueryOption::InAccountOnly(value.to_string()))),
+        "inacct:" => return Ok(Err(QueryOption::InAccount(value.to_string()))),
+        "desc:" => Query::Desc(try!(query_regex(value))),
+        "acct:" => Query::Acct(try!(query_regex(value))),
+        "code:" => Query::Code(try!(query_regex(value))),
+        "cur:" => Query::Symbol(try!(query_regex(&format!("^(?:{})$", value)))),
+        "date:" => Query::Date(try!(dates::parse_date_span(*day, value).map_err(ParseQueryError::new))),
+        "date2:" => Query::Date2(try!(dates::parse_date_span(*day, value).map_err(ParseQueryError::new))),
+        "status:" => Query::Status(try!(parse_status(value))),
+        "real:" => Query::Real(try!(parse_bool(value))),
+        "empty:" => Query::Empty(try!(parse_bool(value))),
+        "amt:" => try!(parse_amount_query(value)),
+        "depth:" => match usize::from_str(value) {
+            Ok(d) => Query::Depth(d),
+            Err(_) => return Err(ParseQueryError::new(format!("depth must be a non-negative integer: '{}'", value)))
+        },
+        "tag:" => match value.find('=') {
+            Some(i) => Query::Tag(try!(query_regex(&value[..i])), Some(try!(query_regex(&value[i + 1..])))),
+            None => Query::Tag(try!(query_regex(value)), None)
+        },
+        _ => Query::Acct(try!(query_regex(term)))
+    };
+    Ok(Ok(q))
+}
+
+fn parse_status(s: &str) -> Result<ClearedStatus, ParseQueryError> {
+    match s {
+        "*" | "1" => Ok(ClearedStatus::Cleared),
+        "!" => Ok(ClearedStatus::Pending),
+        "" | "0" => Ok(ClearedStatus::Uncleared),
+        _ => Err(ParseQueryError::new(format!("unknown status '{}', expected *, ! or nothing", s)))
+    }
+}
+
+/// Parses the argument of `amt:`. Without an explicit sign the comparison is
+/// made on absolute values, so `amt:<10` matches both 5 and -5.
+fn parse_amount_query(s: &str) -> Result<Query, ParseQueryError> {
+    let ops = [("<=", OrdPlus::LtEq, OrdPlus::AbsLtEq), ("<", OrdPlus::Lt, OrdPlus::AbsLt),
+               (">=", OrdPlus::GtEq, OrdPlus::AbsGtEq), (">", OrdPlus::Gt, OrdPlus::AbsGt),
+               ("=", OrdPlus::Eq, OrdPlus::AbsEq)];
+    let (signed, abs, rest) = ops.iter()
+        .find(|&&(p, _, _)| s.starts_with(p))
+        .map(|&(p, ref signed, ref abs)| (signed.clone(), abs.clone(), &s[p.len()..]))
+        .unwrap_or((OrdPlus::Eq, OrdPlus::AbsEq, s));
+    let (ord, number) = if rest.starts_with('+') {
+        (signed, &rest[1..])
+    } else if rest.starts_with('-') {
+        (signed, rest)
+    } else {
+        (abs, rest)
+    };
+    match Decimal::from_str(number) {
+        Ok(d) => Ok(Query::Amount(ord, Quantity(d))),
+        Err(e) => Err(ParseQueryError::new(format!("could not parse amount '{}': {}", s, e)))
+    }
 }
 
 pub fn in_account(query_opts: &Vec<QueryOption>) -> Option<(String, bool)> {
@@ -63,7 +241,7 @@ pub fn in_account(query_opts: &Vec<QueryOption>) -> Option<(String, bool)> {
 }
 
 pub fn account_name_level(account_name: String) -> usize {
-    return 0;
+    AccountName::new(account_name).depth()
 }
 
 pub fn same<T: Eq>(vec: &Vec<T>) -> bool {
@@ -85,13 +263,18 @@ fn compare_amount(ord: OrdPlus, qnt: Quantity, amt: Amount) -> bool {
     }
 }
 
+fn matches_tags(name: &Regex, value: &Option<Regex>, tags: &Vec<Tag>) -> bool {
+    tags.iter().any(|t| name.is_match(t.name()) && value.as_ref().map_or(true, |v| v.is_match(t.value())))
+}
+
 impl Query {
     pub fn matches_account(&self, account_name: String) -> bool {
         match *self {
             Query::None => false,
-            Query::Not(ref x) => Query::matches_account(x.as_ref(), account_name),
+            Query::Not(ref x) => !Query::matches_account(x.as_ref(), account_name),
             Query::Or(ref xs) => xs.iter().any(|x|  Query::matches_account(x, account_name.clone())),
             Query::And(ref xs) => xs.iter().all(|x| Query::matches_account(x, account_name.clone())),
+            Query::Acct(ref r) => r.is_match(&account_name),
             Query::Depth(d) => account_name_level(account_name) <= d,
             Query::Tag(_, _) => false,
             _ => true,
@@ -106,7 +289,7 @@ impl Query {
             &Query::Or(ref qs) => qs.iter().any(|x| x.matches_amount(amount)),
             &Query::And(ref qs) => qs.iter().all(|x| x.matches_amount(amount)),
             &Query::Amount(ref ord, ref n) => compare_amount(ord.clone(), n.clone(), amount.clone()),
-            &Query::Symbol(ref r) => true,
+            &Query::Symbol(ref r) => r.is_match(amount.commodity.symbol()),
             _ => true
         }
     }
@@ -119,62 +302,132 @@ impl Query {
         }
     }
 
-    fn simplify_helper_and(xs: &Vec<Query>) -> Query {
-        match xs.as_slice() {
-            [] => Query::Any,
-            [ref q] => Query::simplify_helper(q),
-            q => {
-                if same(xs) {
-                    xs.first().unwrap().simplify()
-                } else if q.iter().any(|x| *x == Query::None) {
-                    Query::None
-                } else if q.iter().all(|x| true) {
-                    Query::None
-                } else {
-                    Query::None
-                }
-            }
+    /// Whether the query matches the posting. Status and tags are inherited
+    /// from the posting's transaction; code, description and dates are taken from it.
+    pub fn matches_posting(&self, posting: &Posting) -> bool {
+        let transaction = posting.transaction.as_ref();
+        match self {
+            &Query::Any => true,
+            &Query::None => false,
+            &Query::Not(ref q) => !q.matches_posting(posting),
+            &Query::Or(ref qs) => qs.iter().any(|x| x.matches_posting(posting)),
+            &Query::And(ref qs) => qs.iter().all(|x| x.matches_posting(posting)),
+            &Query::Code(ref r) => r.is_match(transaction.map_or("", |t| &t.code)),
+            &Query::Desc(ref r) => r.is_match(transaction.map_or("", |t| &t.description)),
+            &Query::Acct(ref r) => r.is_match(posting.account.as_str()),
+            &Query::Date(ref span) => posting.date().map_or(false, |d| span.contains(&d)),
+            &Query::Date2(ref span) => posting.date2().map_or(false, |d| span.contains(&d)),
+            &Query::Status(ref s) => posting.status() == *s,
+            &Query::Real(v) => posting.is_real() == v,
+            &Query::Amount(_, _) | &Query::Symbol(_) => self.matches_mixed_amount(&posting.amount),
+            &Query::Empty(v) => posting.amount.is_zero() == v,
+            &Query::Depth(d) => posting.account.depth() <= d,
+            &Query::Tag(ref n, ref v) => matches_tags(n, v, &posting.all_tags())
         }
     }
 
-    fn simplify_helper_or(xs: &Vec<Query>) -> Query {
-        match xs.as_slice() {
-            [] => Query::Any,
-            [ref q] => Query::simplify_helper(q),
-            q => {
-                if same(xs) {
-                    xs.first().unwrap().simplify()
-                } else if q.iter().any(|x| *x == Query::Any) {
-                    Query::None
-                } else {
-                    Query::Or(q.iter().filter(|&x| *x != Query::None).map(|x| x.simplify()).collect())
+    /// Whether the query matches the transaction. Account, amount and
+    /// commodity terms match if any of the transaction's postings match.
+    pub fn matches_transaction(&self, transaction: &Transaction) -> bool {
+        match self {
+            &Query::Any => true,
+            &Query::None => false,
+            &Query::Not(ref q) => !q.matches_transaction(transaction),
+            &Query::Or(ref qs) => qs.iter().any(|x| x.matches_transaction(transaction)),
+            &Query::And(ref qs) => qs.iter().all(|x| x.matches_transaction(transaction)),
+            &Query::Code(ref r) => r.is_match(&transaction.code),
+            &Query::Desc(ref r) => r.is_match(&transaction.description),
+            &Query::Acct(_) | &Query::Amount(_, _) | &Query::Symbol(_) =>
+                transaction.postings.iter().any(|p| self.matches_posting(p)),
+            &Query::Date(ref span) => span.contains(&transaction.date),
+            &Query::Date2(ref span) => span.contains(&transaction.date2.unwrap_or(transaction.date)),
+            &Query::Status(ref s) => transaction.status == *s,
+            &Query::Real(v) => v == transaction.postings.iter().any(|p| p.is_real()),
+            &Query::Empty(v) => v == transaction.postings.iter().any(|p| p.amount.is_zero()),
+            &Query::Depth(_) => true,
+            &Query::Tag(ref n, ref v) => matches_tags(n, v, &transaction.all_tags())
+        }
+    }
+
+    /// Conjunction: flattens nested `And`s, drops `Any`, is `None` as soon as
+    /// any term is, removes duplicates and merges depth limits and date spans
+    fn simplify_and(xs: &Vec<Query>) -> Query {
+        let mut terms: Vec<Query> = Vec::new();
+        let mut depth: Option<usize> = None;
+        let mut date: Option<DateSpan> = None;
+        let mut date2: Option<DateSpan> = None;
+        for q in xs.iter().map(|x| x.simplify()) {
+            let qs = match q {
+                Query::And(qs) => qs,
+                q => vec!(q)
+            };
+            for q in qs {
+                match q {
+                    Query::Any => (),
+                    Query::None => return Query::None,
+                    Query::Depth(d) => depth = Some(depth.map_or(d, |x| if d < x { d } else { x })),
+                    Query::Date(s) => date = Some(date.map_or(s.clone(), |x| x.intersect(&s))),
+                    Query::Date2(s) => date2 = Some(date2.map_or(s.clone(), |x| x.intersect(&s))),
+                    q => if !terms.contains(&q) { terms.push(q) }
                 }
             }
         }
+        terms.extend(date.map(Query::Date));
+        terms.extend(date2.map(Query::Date2));
+        terms.extend(depth.map(Query::Depth));
+        match terms.len() {
+            0 => Query::Any,
+            1 => terms.remove(0),
+            _ => Query::And(terms)
+        }
     }
 
-    fn simplify_helper(query: &Query) -> Query {
-        match query {
-            &Query::And(ref xs) => Query::simplify_helper_and(xs),
-            &Query::Or(ref xs) => Query::simplify_helper_or(xs),
-            q => q.clone()
+    /// Disjunction: flattens nested `Or`s, drops `None`, is `Any` as soon as
+    /// any term is, removes duplicates and merges depth limits
+    fn simplify_or(xs: &Vec<Query>) -> Query {
+        let mut terms: Vec<Query> = Vec::new();
+        let mut depth: Option<usize> = None;
+        for q in xs.iter().map(|x| x.simplify()) {
+            let qs = match q {
+                Query::Or(qs) => qs,
+                q => vec!(q)
+            };
+            for q in qs {
+                match q {
+                    Query::None => (),
+                    Query::Any => return Query::Any,
+                    Query::Depth(d) => depth = Some(depth.map_or(d, |x| if d > x { d } else { x })),
+                    q => if !terms.contains(&q) { terms.push(q) }
+                }
+            }
+        }
+        terms.extend(depth.map(Query::Depth));
+        match terms.len() {
+            0 => Query::None,
+            1 => terms.remove(0),
+            _ => Query::Or(terms)
         }
     }
 
-    /// Simplify the query as much as possible
+    /// Simplify the query as much as possible without changing what it matches
     pub fn simplify(&self) -> Query {
-        let q = Query::simplify_helper(self);
-        if *self == q {
-            self.clone()
-        } else {
-            q.simplify()
+        match self {
+            &Query::And(ref xs) => Query::simplify_and(xs),
+            &Query::Or(ref xs) => Query::simplify_or(xs),
+            &Query::Not(ref q) => match q.simplify() {
+                Query::Any => Query::None,
+                Query::None => Query::Any,
+                Query::Not(r) => (*r).clone(),
+                r => Query::Not(Rc::new(r))
+            },
+            q => q.clone()
         }
     }
 
     pub fn filter<F>(&self, pred: F) -> Query where F : Fn(&Query) -> bool {
         match self {
             &Query::And(ref qs) => Query::And(qs.iter().filter(|&x| pred(x)).map(|x| x.clone()).collect()),
-            &Query::Or(ref qs) => Query::Or(qs.iter().filter(|&x| pred(x)).map(|x| x.clone()).collect()),
+            &Query::Or(ref qs) if qs.iter().any(|x| pred(x)) => Query::Or(qs.iter().filter(|&x| pred(x)).map(|x| x.clone()).collect()),
             _ => if pred(self) { self.clone() } else { Query::Any }
         }.simplify()
     }
@@ -191,6 +444,21 @@ impl Query {
         }
     }
 
+    /// The date span matched by the query's primary (or, if `secondary` is
+    /// set, secondary) date terms
+    pub fn date_span(&self, secondary: bool) -> DateSpan {
+        match self {
+            &Query::Date(ref span) if !secondary => span.clone(),
+            &Query::Date2(ref span) if secondary => span.clone(),
+            &Query::And(ref qs) => qs.iter().map(|x| x.date_span(secondary)).fold(DateSpan::everything(), |a, b| a.intersect(&b)),
+            &Query::Or(ref qs) if !qs.is_empty() => {
+                let spans: Vec<DateSpan> = qs.iter().map(|x| x.date_span(secondary)).collect();
+                spans.iter().skip(1).fold(spans[0].clone(), |a, b| a.union(b))
+            },
+            _ => DateSpan::everything()
+        }
+    }
+
     pub fn is_depth(&self) -> bool {
         match self {
             &Query::Depth(_) => true,
@@ -226,3 +494,121 @@ fn simplify_test() {
 
     assert!(sq == Query::Desc(Regex::new("b b").unwrap()))
 }
+
+#[test]
+fn parse_query_test() {
+    let day = Local::today();
+    let (q, opts) = parse_query(day, String::from("expenses 'assets:my bank' desc:shop not:code:12 amt:<-5 inacct:assets")).unwrap();
+    assert!(opts == vec!(QueryOption::InAccount(String::from("assets"))));
+    assert!(q == Query::And(vec!(
+        Query::Or(vec!(Query::Acct(Regex::new("(?i)expenses").unwrap()), Query::Acct(Regex::new("(?i)assets:my bank").unwrap()))),
+        Query::Desc(Regex::new("(?i)shop").unwrap()),
+        Query::Not(Rc::new(Query::Code(Regex::new("(?i)12").unwrap()))),
+        Query::Amount(OrdPlus::Lt, Quantity(Decimal::new(-5, 0)))
+    )));
+
+    assert!(parse_query(day, String::from("")).unwrap().0 == Query::Any);
+    assert!(parse_query(day, String::from("status:!")).unwrap().0 == Query::Status(ClearedStatus::Pending));
+    assert!(parse_query(day, String::from("amt:10")).unwrap().0 == Query::Amount(OrdPlus::AbsEq, Quantity(Decimal::new(10, 0))));
+    assert!(parse_query(day, String::from("depth:x")).is_err());
+    assert!(parse_query(day, String::from("desc:(")).is_err());
+    assert!(parse_query(day, String::from("real:f")).unwrap().0 == Query::Real(false));
+    assert!(parse_query(day, String::from("real:yes")).is_err());
+    assert!(parse_query(day, String::from("empty:foo")).is_err());
+}
+
+#[test]
+fn parse_date_query_test() {
+    use chrono::TimeZone;
+    let day = Local.ymd(2016, 5, 10);
+    let (q, _) = parse_query(day, String::from("date:2016 'date2:from 2016/02 to 2016/03' acct:x")).unwrap();
+    assert!(q.date_span(false) == DateSpan::new(Some(Local.ymd(2016, 1, 1)), Some(Local.ymd(2017, 1, 1))));
+    assert!(q.date_span(true) == DateSpan::new(Some(Local.ymd(2016, 2, 1)), Some(Local.ymd(2016, 3, 1))));
+    assert!(parse_query(day, String::from("date:someday")).is_err());
+}
+
+#[test]
+fn matches_posting_test() {
+    use chrono::TimeZone;
+    use parser::parse_journal;
+    let journal = parse_journal("2016/02/01 * (7) Rent  ; kind:home\n    expenses:rent  500 EUR\n    ! assets:bank\n").unwrap();
+    let t = &journal.transactions[0];
+    let (rent, bank) = (&t.postings[0], &t.postings[1]);
+    let q = |s: &str| parse_query(Local.ymd(2016, 3, 1), String::from(s)).unwrap().0;
+
+    assert!(q("rent").matches_posting(rent) && !q("rent").matches_posting(bank));
+    assert!(q("desc:rent code:7 date:2016/02").matches_posting(bank));
+    assert!(q("status:*").matches_posting(rent) && q("status:!").matches_posting(bank));
+    assert!(q("tag:kind=home").matches_posting(rent));
+    assert!(q("cur:eur amt:>=500").matches_posting(rent) && !q("cur:eu").matches_posting(rent));
+    assert!(q("depth:1").matches_posting(rent) == false);
+    assert!(q("not:acct:bank").matches_posting(rent) && !q("not:acct:bank").matches_posting(bank));
+
+    assert!(q("acct:bank status:*").matches_transaction(t));
+    assert!(!q("'date:last month' status:!").matches_transaction(t));
+    assert!(q("real:").matches_transaction(t) && !q("real:0").matches_transaction(t));
+
+    let mixed = parse_journal("2016/02/02 x\n    a  0 EUR\n    b\n    (memo)  1 EUR\n").unwrap();
+    let m = &mixed.transactions[0];
+    assert!(!q("real:0").matches_transaction(m) && q("real:0").matches_posting(&m.postings[2]));
+    assert!(q("empty:").matches_posting(&m.postings[0]) && !q("empty:").matches_posting(&m.postings[2]));
+    let full = parse_journal("2016/02/03 y\n    a  1 EUR\n    b  -1 EUR\n").unwrap();
+    let f = &full.transactions[0];
+    assert!(q("empty:").matches_transaction(m) && !q("empty:").matches_transaction(f));
+    assert!(q("empty:0").matches_transaction(f) && !q("empty:0").matches_transaction(m));
+}
+
+#[test]
+fn simplify_rules_test() {
+    let a = Query::Desc(Regex::new("a").unwrap());
+    let b = Query::Code(Regex::new("b").unwrap());
+    let not = |q: Query| Query::Not(Rc::new(q));
+
+    assert!(Query::And(vec!(Query::Any, a.clone(), Query::And(vec!(b.clone(), a.clone())))).simplify() == Query::And(vec!(a.clone(), b.clone())));
+    assert!(Query::And(vec!(a.clone(), Query::None)).simplify() == Query::None);
+    assert!(Query::Or(vec!(Query::None, a.clone(), Query::Or(vec!(a.clone())))).simplify() == a);
+    assert!(Query::Or(vec!(a.clone(), Query::Any)).simplify() == Query::Any);
+    assert!(Query::Or(vec!()).simplify() == Query::None);
+    assert!(Query::And(vec!()).simplify() == Query::Any);
+    assert!(not(not(a.clone())).simplify() == a);
+    assert!(not(Query::And(vec!(Query::Any))).simplify() == Query::None);
+    assert!(Query::And(vec!(Query::Depth(3), a.clone(), Query::Depth(2))).simplify() == Query::And(vec!(a.clone(), Query::Depth(2))));
+    assert!(Query::Or(vec!(Query::Depth(3), Query::Depth(2))).simplify() == Query::Depth(3));
+}
+
+#[test]
+fn simplify_preserves_semantics_test() {
+    use chrono::TimeZone;
+    use parser::parse_journal;
+    let journal = parse_journal("2016/02/01 * (7) Rent  ; kind:home\n    expenses:rent  500 EUR\n    ! assets:bank:checking\n\n\
+                                 2016/03/05 Shop\n    expenses:food  $5\n    (budget:food)  $-5\n    assets:cash\n").unwrap();
+    let day = Local.ymd(2016, 3, 10);
+    let atoms: Vec<Query> = vec!("rent", "desc:shop", "status:*", "status:!", "tag:kind", "amt:>10", "cur:eur",
+                                 "real:", "depth:1", "depth:2", "date:2016/02", "'date:from 2016/2/15'", "code:7")
+        .into_iter().map(|x| parse_query(day, String::from(x)).unwrap().0)
+        .chain(vec!(Query::Any, Query::None).into_iter()).collect();
+
+    let mut queries = atoms.clone();
+    for x in atoms.iter() {
+        queries.push(Query::Not(Rc::new(Query::Not(Rc::new(x.clone())))));
+        for y in atoms.iter() {
+            queries.push(Query::And(vec!(x.clone(), y.clone())));
+            queries.push(Query::Or(vec!(x.clone(), Query::And(vec!(y.clone(), Query::Any)))));
+            queries.push(Query::And(vec!(Query::Or(vec!(x.clone(), y.clone())), Query::Not(Rc::new(y.clone())), x.clone())));
+        }
+    }
+
+    let postings = journal.postings();
+    for q in queries.iter() {
+        let s = q.simplify();
+        for p in postings.iter() {
+            assert!(q.matches_posting(p) == s.matches_posting(p), "{:?} / {:?}", q, s);
+            assert!(q.matches_account(p.account.as_str().to_string()) == s.matches_account(p.account.as_str().to_string()), "{:?} / {:?}", q, s);
+            assert!(q.matches_mixed_amount(&p.amount) == s.matches_mixed_amount(&p.amount), "{:?} / {:?}", q, s);
+        }
+        for t in journal.transactions.iter() {
+            assert!(q.matches_transaction(t) == s.matches_transaction(t), "{:?} / {:?}", q, s);
+        }
+        assert!(s.simplify() == s);
+    }
+}
@@ -1,12 +1,85 @@
+use std::fmt;
+use std::error;
+use amount::{ Amount, MixedAmount };
+use decimal::Decimal;
 use posting::ClearedStatus;
 use posting::Posting;
+use posting::PostingType;
 use posting::Tag;
 use chrono::Date;
 use chrono::Local;
+use dates::{ DateSpan, PeriodExpression, parse_period_expression };
 
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub struct GenericSourcePos(String, usize, usize);
 
+impl GenericSourcePos {
+    pub fn new(file: String, line: usize, column: usize) -> GenericSourcePos {
+        GenericSourcePos(file, line, column)
+    }
+
+    pub fn file(&self) -> &str {
+        &self.0
+    }
+
+    pub fn line(&self) -> usize {
+        self.1
+    }
+
+    pub fn column(&self) -> usize {
+        self.2
+    }
+}
+
+impl fmt::Display for GenericSourcePos {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.0, self.1, self.2)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BalanceErrorKind {
+    MultipleMissingAmounts(String),
+    Unbalanced(String, String),
+}
+
+impl BalanceErrorKind {
+    fn desc(&self) -> String {
+        match *self {
+            BalanceErrorKind::MultipleMissingAmounts(ref group) =>
+                format!("could not balance transaction: more than one {} posting has no amount", group),
+            BalanceErrorKind::Unbalanced(ref group, ref residual) =>
+                format!("could not balance transaction: {} postings are off by {}", group, residual),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BalanceError {
+    pub kind: BalanceErrorKind,
+    pub source_pos: GenericSourcePos,
+    desc: String,
+}
+
+impl BalanceError {
+    fn new(kind: BalanceErrorKind, source_pos: GenericSourcePos) -> Self {
+        let desc = kind.desc();
+        BalanceError { kind: kind, source_pos: source_pos, desc: desc }
+    }
+}
+
+impl fmt::Display for BalanceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.source_pos, self.desc)
+    }
+}
+
+impl error::Error for BalanceError {
+    fn description(&self) -> &str {
+        &self.desc
+    }
+}
+
 #[derive(Clone, PartialEq, Eq)]
 pub struct Transaction {
     pub index: usize,
@@ -26,6 +99,76 @@ impl Transaction {
     pub fn all_tags(&self) -> Vec<Tag> {
         self.tags.iter().chain(self.postings.iter().flat_map(|x| x.tags.iter())).map(|x| x.clone()).collect()
     }
+
+    /// Links every posting back to a copy of this transaction, so that
+    /// postings can inherit the transaction's status and tags
+    pub fn tie_knot(mut self) -> Transaction {
+        let mut t = self.clone();
+        for p in t.postings.iter_mut() {
+            p.transaction = None;
+        }
+        for p in self.postings.iter_mut() {
+            p.transaction = Some(t.clone());
+        }
+        self
+    }
+
+    /// Infers the amount of a posting written without one and checks that
+    /// the postings sum to zero at cost.
+    ///
+    /// Real postings and balanced virtual (`[bracketed]`) postings are
+    /// balanced separately; unbalanced virtual postings are ignored. Each
+    /// group may contain one posting without an amount, which receives the
+    /// negated sum of the others. Residuals too small to show at the
+    /// commodity's display precision are tolerated.
+    pub fn balance(&self) -> Result<Transaction, BalanceError> {
+        let mut t = self.clone();
+        try!(t.balance_group("real", |p| p.posting_type == PostingType::Regular));
+        try!(t.balance_group("balanced virtual", |p| p.posting_type == PostingType::BalancedVirtual));
+        Ok(t.tie_knot())
+    }
+
+    fn balance_group<F>(&mut self, group: &str, in_group: F) -> Result<(), BalanceError> where F : Fn(&Posting) -> bool {
+        let missing: Vec<usize> = (0..self.postings.len())
+            .filter(|&i| in_group(&self.postings[i]) && self.postings[i].amount.0.is_empty())
+            .collect();
+        if missing.len() > 1 {
+            return Err(BalanceError::new(BalanceErrorKind::MultipleMissingAmounts(group.to_string()), self.source_pos.clone()));
+        }
+
+        let sum = self.postings.iter()
+            .filter(|p| in_group(p))
+            .fold(MixedAmount(vec!()), |acc, p| acc + p.amount.cost());
+
+        match missing.first() {
+            Some(&i) => {
+                self.postings[i].amount = if sum.0.is_empty() { MixedAmount(vec!(Amount::new())) } else { -sum };
+                self.postings[i].amount_inferred = true;
+                Ok(())
+            },
+            None => {
+                let written: Vec<&Amount> = self.postings.iter().filter(|p| in_group(p)).flat_map(|p| p.amount.0.iter()).collect();
+                let residual = MixedAmount(sum.0.into_iter().filter(|x| {
+                    let precision = written.iter()
+                        .filter(|a| a.commodity == x.commodity)
+                        .map(|a| a.style.precision)
+                        .max()
+                        .unwrap_or(x.style.precision);
+                    !is_below_precision(x, precision)
+                }).collect());
+                if residual.0.is_empty() {
+                    Ok(())
+                } else {
+                    Err(BalanceError::new(BalanceErrorKind::Unbalanced(group.to_string(), format!("{}", residual.with_full_precision())), self.source_pos.clone()))
+                }
+            }
+        }
+    }
+}
+
+/// Whether the amount rounds to zero when shown with `precision` decimal places
+fn is_below_precision(amount: &Amount, precision: usize) -> bool {
+    amount.quantity.0.abs() * 2 < Decimal::new(1, precision as u32)
 }
 
 #[derive(Clone, PartialEq, Eq)]
@@ -37,5 +180,92 @@ pub struct ModifierTransaction {
 #[derive(Clone, PartialEq, Eq)]
 pub struct PeriodicTransaction {
     pub periodic_expression: String,
+    pub source_pos: GenericSourcePos,
     pub postings: Vec<Posting>
 }
+
+impl PeriodicTransaction {
+    /// The period expression and the description that may follow it after
+    /// two spaces, as in `~ monthly  rent`
+    fn split_expression(&self) -> (&str, &str) {
+        match self.periodic_expression.find("  ") {
+            Some(i) => (&self.periodic_expression[..i], self.periodic_expression[i..].trim()),
+            None => (&self.periodic_expression, "")
+        }
+    }
+
+    /// The period expression, with relative dates resolved against `day`
+    pub fn period_expression(&self, day: Date<Local>) -> Result<PeriodExpression, String> {
+        parse_period_expression(day, self.split_expression().0)
+            .map_err(|e| format!("{}: {}", self.source_pos, e))
+    }
+
+    /// One transaction for each occurrence of the period expression within
+    /// `span`, with the rule's postings and description. Generated
+    /// transactions have no index, carry a `generated-transaction` tag with
+    /// the expression, and are not balanced.
+    pub fn generate(&self, span: &DateSpan, day: Date<Local>) -> Result<Vec<Transaction>, String> {
+        let expression = try!(self.period_expression(day));
+        let dates = try!(expression.occurrences(span).map_err(|e| format!("{}: {}", self.source_pos, e)));
+        let description = self.split_expression().1;
+        Ok(dates.into_iter().map(|date| Transaction {
+            index: 0,
+            source_pos: self.source_pos.clone(),
+            date: date,
+            date2: None,
+            status: ClearedStatus::Uncleared,
+            code: String::new(),
+            description: description.to_string(),
+            comment: String::new(),
+            tags: vec!(Tag::new(String::from("generated-transaction"), format!("~ {}", self.periodic_expression))),
+            postings: self.postings.iter().map(|p| {
+                let mut p = p.clone();
+                p.transaction = None;
+                p
+            }).collect(),
+            preceding_comment_lines: String::new()
+        }.tie_knot()).collect())
+    }
+}
+
+#[test]
+fn balance_test() {
+    use parser::parse_journal;
+    use commodity::Commodity;
+    use quantity::Quantity;
+    let journal = parse_journal("2016/01/01 buy\n    assets:stock  10 AAPL @ $1.50\n    assets:cash\n    [budget]  $5\n    [saved]\n    (memo)  $3\n").unwrap();
+    let t = journal.transactions[0].balance().unwrap();
+    let usd = Commodity::new(String::from("$"));
+    assert!(t.postings[1].amount.get(&usd).unwrap().quantity == Quantity(Decimal::new(-1500, 2)));
+    assert!(t.postings[3].amount.get(&usd).unwrap().quantity == Quantity(Decimal::new(-5, 0)));
+    assert!(t.postings[1].transaction.as_ref().unwrap().postings[1].amount == t.postings[1].amount);
+    let total = parse_journal("2016/01/01 buy\n    assets:stock  10 AAPL @@ $15\n    assets:cash  $-15\n").unwrap();
+    assert!(total.transactions[0].balance().is_ok());
+
+    let rounded = parse_journal("2016/01/01 x\n    a  $1.00\n    b  $-0.996\n").unwrap();
+    let r = rounded.transactions[0].balance();
+    assert!(r.err().unwrap().kind == BalanceErrorKind::Unbalanced(String::from("real"), String::from("$0.004")));
+    let tolerated = parse_journal("2016/01/01 x\n    a  10 X @ $0.3333\n    b  $-3.33\n").unwrap();
+    assert!(tolerated.transactions[0].balance().is_ok());
+
+    let err = parse_journal("\n2016/01/01 x\n    a  $1\n    b  $2\n").unwrap().transactions[0].balance().err().unwrap();
+    assert!(err.kind == BalanceErrorKind::Unbalanced(String::from("real"), String::from("$3")));
+    assert!(format!("{}", err) == ":2:1: could not balance transaction: real postings are off by $3");
+    assert!(parse_journal("2016/01/01 x\n    a\n    b\n").unwrap().transactions[0].balance().is_err());
+}
+
+#[test]
+fn periodic_transaction_test() {
+    use parser::parse_journal;
+    use chrono::TimeZone;
+    let journal = parse_journal("~ every 15th day of month from 2016/01/01  rent\n    expenses:rent  $500\n    assets:bank\n").unwrap();
+    let periodic = &journal.periodic_transactions[0];
+    let span = DateSpan::new(Some(Local.ymd(2016, 1, 1)), Some(Local.ymd(2016, 3, 1)));
+    let ts = periodic.generate(&span, Local.ymd(2016, 6, 1)).unwrap();
+    assert!(ts.len() == 2);
+    assert!(ts[1].date == Local.ymd(2016, 2, 15));
+    assert!(ts[0].description == "rent");
+    assert!(ts[0].tags[0].value() == "~ every 15th day of month from 2016/01/01  rent");
+    assert!(ts[0].balance().unwrap().postings[1].amount == -ts[0].postings[0].amount.clone());
+    assert!(periodic.generate(&DateSpan::everything(), Local.ymd(2016, 6, 1)).is_err());
+}
@@ -0,0 +1,135 @@
+use std::str;
+use chrono::{ Date, Local };
+
+use amount::{ Amount, AmountStyle, MixedAmount };
+use commodity::Commodity;
+use parser;
+use price::PriceDb;
+use quantity::Quantity;
+
+/// How amounts are converted for reporting. Each mode may name a target
+/// commodity; without one, amounts are valued in the commodity of their
+/// latest market price.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Valuation {
+    /// Convert to cost using transaction prices; with a target commodity the
+    /// cost is then valued at the posting date
+    Cost(Option<Commodity>),
+    /// Market value at the posting date
+    Then(Option<Commodity>),
+    /// Market value at the last day of the report period
+    End(Option<Commodity>),
+    /// Market value today
+    Now(Option<Commodity>),
+    /// Market value at the given date
+    At(Date<Local>, Option<Commodity>)
+}
+
+impl Valuation {
+    pub fn target(&self) -> Option<&Commodity> {
+        match *self {
+            Valuation::Cost(ref c) | Valuation::Then(ref c) | Valuation::End(ref c) |
+            Valuation::Now(ref c) | Valuation::At(_, ref c) => c.as_ref()
+        }
+    }
+
+    /// Values an amount posted on `posting_date`. `period_last` is the last
+    /// day of the report period. Amounts without a known price are returned unchanged.
+    pub fn value_amount(&self, amount: &Amount, prices: &PriceDb, posting_date: Date<Local>, period_last: Date<Local>, today: Date<Local>) -> Amount {
+        match *self {
+            Valuation::Cost(None) => amount.cost(),
+            Valuation::Cost(ref c) => market_value(&amount.cost(), c.as_ref(), prices, posting_date),
+            Valuation::Then(ref c) => market_value(amount, c.as_ref(), prices, posting_date),
+            Valuation::End(ref c) => market_value(amount, c.as_ref(), prices, period_last),
+            Valuation::Now(ref c) => market_value(amount, c.as_ref(), prices, today),
+            Valuation::At(d, ref c) => market_value(amount, c.as_ref(), prices, d)
+        }
+    }
+
+    pub fn value_mixed_amount(&self, amount: &MixedAmount, prices: &PriceDb, posting_date: Date<Local>, period_last: Date<Local>, today: Date<Local>) -> MixedAmount {
+        MixedAmount(amount.0.iter().map(|x| self.value_amount(x, prices, posting_date, period_last, today)).collect()).normalise()
+    }
+}
+
+/// Converts `amount` into `target` at the price in effect on `date`, shown
+/// in the target commodity's style
+fn market_value(amount: &Amount, target: Option<&Commodity>, prices: &PriceDb, date: Date<Local>) -> Amount {
+    let target = match target.cloned().or_else(|| prices.default_target(&amount.commodity, &date)) {
+        Some(c) => c,
+        None => return amount.clone()
+    };
+    match amount.commodity.conversion_rate(&target, &date, prices) {
+        Some(rate) => {
+            let mut r = amount.with_commodity(target);
+            r.style = prices.style(&r.commodity).cloned().unwrap_or(AmountStyle::new());
+            r.quantity = Quantity((amount.quantity.0 * rate).normalize());
+            r
+        },
+        None => amount.clone()
+    }
+}
+
+/// Parses a valuation as given on the command line: `cost`, `then`, `end`,
+/// `now` or a full date, optionally followed by a comma and a target commodity,
+/// e.g. `end,EUR`
+impl str::FromStr for Valuation {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Valuation, String> {
+        let (mode, commodity) = match s.find(',') {
+            Some(i) => (&s[..i], Some(Commodity::new(s[i + 1..].trim().to_string()))),
+            None => (s, None)
+        };
+        match mode.trim() {
+            "cost" => Ok(Valuation::Cost(commodity)),
+            "then" => Ok(Valuation::Then(commodity)),
+            "end" => Ok(Valuation::End(commodity)),
+            "now" => Ok(Valuation::Now(commodity)),
+            d => match parser::parse_date(d, None) {
+                Some(date) => Ok(Valuation::At(date, commodity)),
+                None => Err(format!("unknown valuation '{}', expected cost, then, end, now or a full date", d))
+            }
+        }
+    }
+}
+
+#[test]
+fn valuation_test() {
+    use chrono::TimeZone;
+    use amount::Side;
+    use decimal::Decimal;
+    use parser::parse_journal;
+    use journal::MarketPrice;
+
+    let mut journal = parse_journal("2016/01/10 buy\n    assets:stock  10 AAPL @ $90\n    assets:cash\n").unwrap();
+    journal.balance_transactions().unwrap();
+    let usd = Commodity::new(String::from("$"));
+    let eur = Commodity::new(String::from("EUR"));
+    let mut price = |d: Date<Local>, c: &str, q: i128, to: &str| {
+        let mut a = Amount::new();
+        a.commodity = Commodity::new(String::from(to));
+        a.quantity = Quantity(Decimal::new(q, 0));
+        journal.add_market_price_mut(MarketPrice { date: d, commodity: Commodity::new(String::from(c)), amount: a });
+    };
+    price(Local.ymd(2016, 1, 1), "AAPL", 100, "$");
+    price(Local.ymd(2016, 3, 1), "AAPL", 120, "$");
+    price(Local.ymd(2016, 1, 1), "$", 2, "EUR");
+
+    let prices = journal.price_db();
+    let stock = &journal.transactions[0].postings[0];
+    let value = |v: &str| {
+        let v: Valuation = v.parse().unwrap();
+        v.value_mixed_amount(&stock.amount, &prices, stock.date().unwrap(), Local.ymd(2016, 2, 29), Local.ymd(2016, 4, 1)).0
+    };
+    let quantity = |v: &str, c: &Commodity| value(v).iter().find(|x| x.commodity == *c).map(|x| x.quantity.clone());
+
+    assert!(quantity("cost", &usd) == Some(Quantity(Decimal::new(900, 0))));
+    assert!(quantity("cost,EUR", &eur) == Some(Quantity(Decimal::new(1800, 0))));
+    assert!(quantity("then", &usd) == Some(Quantity(Decimal::new(1000, 0))));
+    assert!(quantity("end", &usd) == Some(Quantity(Decimal::new(1000, 0))));
+    assert!(quantity("now", &usd) == Some(Quantity(Decimal::new(1200, 0))));
+    assert!(quantity("now,EUR", &eur) == Some(Quantity(Decimal::new(2400, 0))));
+    assert!(value("now")[0].style.commodity_side == Side::Left && !value("now")[0].style.commodity_spaced);
+    assert!(quantity("2015/12/31", &usd) == None);
+    assert!("sometime".parse::<Valuation>().is_err());
+    assert!("03/01".parse::<Valuation>().is_err());
+}